@@ -0,0 +1,105 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use tokio::runtime::Runtime;
+
+use funny_proxy::connection::ConnectionState;
+use funny_proxy::packet::payload::{Encodable, StatusPing};
+use funny_proxy::packet::{Packet, PacketReader, PacketType, PacketWriter};
+use funny_proxy::protocol::ProtocolVersion;
+
+const PACKETS: usize = 10_000;
+
+// frames as a client sends them back to back, the way they pile up in one read
+fn status_pings(count: usize) -> Vec<u8> {
+    let mut data = PacketWriter::create(8);
+    StatusPing { payload: 0x0123456789ABCDEF }.encode(&mut data);
+
+    let ping = Packet { data: data.as_ref().to_vec(), raw_size: 0, packet_type: PacketType::StatusServerboundPing };
+    ping.encode(ProtocolVersion::V1_19_4).unwrap().repeat(count)
+}
+
+fn packet_decode(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let buffer = status_pings(PACKETS);
+
+    let mut group = c.benchmark_group("packet_decode");
+    group.throughput(Throughput::Bytes(buffer.len() as u64));
+    group.bench_function("status_pings", |b| b.iter(|| runtime.block_on(async {
+        let mut offset = 0;
+        while offset < buffer.len() {
+            let packet = Packet::decode(&buffer[offset..], ConnectionState::Status, ProtocolVersion::V1_19_4, None).await.unwrap();
+            offset += packet.raw_size;
+        }
+        black_box(offset)
+    })));
+    group.finish();
+}
+
+fn read_varint(c: &mut Criterion) {
+    let values: [(&str, i32); 3] = [("one_byte", 100), ("three_bytes", 1 << 20), ("five_bytes", -1)];
+
+    let mut group = c.benchmark_group("read_varint");
+    group.throughput(Throughput::Elements(PACKETS as u64));
+    for (name, value) in values {
+        let mut buffer = PacketWriter::create(PACKETS * 5);
+        for _ in 0..PACKETS {
+            buffer.write_var_int(value);
+        }
+
+        group.bench_function(name, |b| b.iter(|| {
+            let mut reader = PacketReader::create(buffer.as_ref());
+            for _ in 0..PACKETS {
+                black_box(reader.read_varint().unwrap());
+            }
+        }));
+    }
+    group.finish();
+}
+
+// the shortcut for single byte varints against reading them in the loop, on the ids and lengths
+// that make up most varints on the wire
+fn read_varint_shortcut(c: &mut Criterion) {
+    let mut buffer = PacketWriter::create(PACKETS * 2);
+    for i in 0..PACKETS {
+        buffer.write_var_int(if i % 10 == 0 { 300 } else { (i % 128) as i32 });
+    }
+
+    let mut group = c.benchmark_group("read_varint_shortcut");
+    group.throughput(Throughput::Elements(PACKETS as u64));
+    group.bench_function("with_shortcut", |b| b.iter(|| {
+        let mut reader = PacketReader::create(buffer.as_ref());
+        for _ in 0..PACKETS {
+            black_box(reader.read_varint().unwrap());
+        }
+    }));
+    group.bench_function("bytewise", |b| b.iter(|| {
+        let mut reader = PacketReader::create(buffer.as_ref());
+        for _ in 0..PACKETS {
+            black_box(reader.read_varint_bytewise().unwrap());
+        }
+    }));
+    group.finish();
+}
+
+fn read_string(c: &mut Criterion) {
+    let strings = [("username", "Steve".to_string()), ("chat_message", "hello there! ".repeat(19)), ("multibyte", "日本語😀".repeat(20))];
+
+    let mut group = c.benchmark_group("read_string");
+    group.throughput(Throughput::Elements(PACKETS as u64));
+    for (name, string) in strings {
+        let mut buffer = PacketWriter::create(PACKETS * (string.len() + 2));
+        for _ in 0..PACKETS {
+            buffer.write_string(&string);
+        }
+
+        group.bench_function(name, |b| b.iter(|| {
+            let mut reader = PacketReader::create(buffer.as_ref());
+            for _ in 0..PACKETS {
+                black_box(reader.read_string(256).unwrap());
+            }
+        }));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, packet_decode, read_varint, read_varint_shortcut, read_string);
+criterion_main!(benches);
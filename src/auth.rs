@@ -0,0 +1,127 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use lazy_static::lazy_static;
+use md5::Md5;
+use num_bigint::BigInt;
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+use uuid::{Builder, Uuid};
+
+const HAS_JOINED_URL: &str = "https://sessionserver.mojang.com/session/minecraft/hasJoined";
+
+lazy_static! {
+    static ref HTTP_CLIENT: reqwest::Client = reqwest::Client::new();
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    Request(reqwest::Error),
+    NotAuthenticated,
+    InvalidUuid(String),
+}
+
+impl Display for AuthError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self, f)
+    }
+}
+
+impl Error for AuthError {}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct GameProfile {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub properties: Vec<ProfileProperty>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ProfileProperty {
+    pub name: String,
+    pub value: String,
+    pub signature: Option<String>,
+}
+
+impl GameProfile {
+    pub fn uuid(&self) -> Result<Uuid, AuthError> {
+        Uuid::parse_str(&self.id).map_err(|_| AuthError::InvalidUuid(self.id.clone()))
+    }
+}
+
+// sha1 of the server id, shared secret and public key, printed as a signed number in hex
+pub fn server_hash(server_id: &str, shared_secret: &[u8], public_key: &[u8]) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(server_id.as_bytes());
+    hasher.update(shared_secret);
+    hasher.update(public_key);
+
+    BigInt::from_signed_bytes_be(&hasher.finalize()).to_str_radix(16)
+}
+
+// name-based uuid the vanilla server assigns to players in offline mode, which unlike
+// Uuid::new_v3 hashes the name without a namespace
+pub fn offline_uuid(name: &str) -> Uuid {
+    let digest = Md5::digest(format!("OfflinePlayer:{}", name).as_bytes());
+
+    Builder::from_md5_bytes(digest.into()).into_uuid()
+}
+
+// asks the session server whether the player joined with this server hash
+pub async fn has_joined(username: &str, server_hash: &str) -> Result<GameProfile, AuthError> {
+    let response = HTTP_CLIENT.get(HAS_JOINED_URL)
+        .query(&[("username", username), ("serverId", server_hash)])
+        .send().await
+        .and_then(|response| response.error_for_status())
+        .map_err(AuthError::Request)?;
+
+    // the session server answers with no content when the player isn't authenticated
+    if response.status() != reqwest::StatusCode::OK {
+        return Err(AuthError::NotAuthenticated);
+    }
+
+    response.json().await.map_err(AuthError::Request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the well known digests of the names alone, covering a positive, a negative and a short hash
+    #[test]
+    fn server_hashes_are_signed_hex() {
+        assert_eq!(server_hash("Notch", &[], &[]), "4ed1f46bbe04bc756bcb17c0c7ce3e4632f06a48");
+        assert_eq!(server_hash("jeb_", &[], &[]), "-7c9d5b0044c130109a5d7b5fb5c317c02b4e28c1");
+        assert_eq!(server_hash("simon", &[], &[]), "88e16a1019277b15d58faf0541e11910eb756f6");
+    }
+
+    #[test]
+    fn server_hashes_cover_the_secret_and_key() {
+        assert_eq!(server_hash("", b"Not", b"ch"), server_hash("Notch", &[], &[]));
+        assert_ne!(server_hash("", b"secret", b"key"), server_hash("", b"secret", b"other key"));
+    }
+
+    #[test]
+    fn profiles_are_read_from_the_session_server_response() {
+        let profile: GameProfile = serde_json::from_str(r#"{
+            "id": "069a79f444e94726a5befca90e38aaf5",
+            "name": "Notch",
+            "properties": [{"name": "textures", "value": "e30=", "signature": "c2ln"}]
+        }"#).unwrap();
+
+        assert_eq!(profile.uuid().unwrap(), Uuid::parse_str("069a79f4-44e9-4726-a5be-fca90e38aaf5").unwrap());
+        assert_eq!(profile.properties[0].name, "textures");
+        assert!(matches!(GameProfile { id: "nope".to_string(), ..profile }.uuid(), Err(AuthError::InvalidUuid(_))));
+    }
+
+    #[test]
+    fn offline_uuids_match_the_vanilla_server() {
+        let uuid = offline_uuid("Notch");
+
+        assert_eq!(uuid, Uuid::parse_str("b50ad385-829d-3141-a216-7e7d7539ba7f").unwrap());
+        assert_eq!(uuid.get_version_num(), 3);
+        assert_eq!(offline_uuid("Notch"), uuid);
+        assert_ne!(offline_uuid("notch"), uuid);
+    }
+}
@@ -0,0 +1,127 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Deserialize;
+use uuid::Uuid;
+
+#[derive(Deserialize, Debug)]
+pub struct Ban {
+    #[serde(default)]
+    pub reason: Option<String>,
+    // unix timestamp in seconds, the ban is permanent without one
+    #[serde(default)]
+    pub expires: Option<u64>,
+}
+
+impl Ban {
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+}
+
+#[derive(Deserialize)]
+struct IpBan {
+    ip: IpAddr,
+    #[serde(flatten)]
+    ban: Ban,
+}
+
+#[derive(Deserialize)]
+struct PlayerBan {
+    name: String,
+    #[serde(default)]
+    uuid: Option<String>,
+    #[serde(flatten)]
+    ban: Ban,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct BanFile {
+    ips: Vec<IpBan>,
+    players: Vec<PlayerBan>,
+}
+
+#[derive(Debug)]
+pub enum BanListError {
+    Io(std::io::Error),
+    Parse(serde_json::Error),
+    InvalidUuid(String),
+}
+
+impl Display for BanListError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BanListError::Io(e) => Display::fmt(e, f),
+            BanListError::Parse(e) => Display::fmt(e, f),
+            BanListError::InvalidUuid(uuid) => write!(f, "invalid uuid {}", uuid)
+        }
+    }
+}
+
+impl Error for BanListError {}
+
+#[derive(Default)]
+pub struct BanList {
+    ips: Vec<(IpAddr, Ban)>,
+    players: Vec<(String, Option<Uuid>, Ban)>,
+}
+
+impl BanList {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<BanList, BanListError> {
+        let json = std::fs::read_to_string(path).map_err(BanListError::Io)?;
+        let file: BanFile = serde_json::from_str(&json).map_err(BanListError::Parse)?;
+
+        let players = file.players.into_iter()
+            .map(|player| match player.uuid {
+                Some(uuid) => match uuid.parse() {
+                    Ok(parsed) => Ok((player.name, Some(parsed), player.ban)),
+                    Err(_) => Err(BanListError::InvalidUuid(uuid))
+                },
+                None => Ok((player.name, None, player.ban))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(BanList {
+            ips: file.ips.into_iter().map(|ip| (ip.ip, ip.ban)).collect(),
+            players,
+        })
+    }
+
+    pub fn ip_ban(&self, ip: IpAddr) -> Option<&Ban> {
+        let now = unix_time();
+
+        self.ips.iter()
+            .find(|(banned, ban)| *banned == ip && !ban.is_expired(now))
+            .map(|(_, ban)| ban)
+    }
+
+    // like the whitelist, the uuid is only given when it was verified and then takes precedence
+    // over the name of entries that have one
+    pub fn player_ban(&self, name: &str, uuid: Option<Uuid>) -> Option<&Ban> {
+        let now = unix_time();
+
+        self.players.iter()
+            .filter(|(_, _, ban)| !ban.is_expired(now))
+            .find(|(banned_name, banned_uuid, _)| match (banned_uuid, uuid) {
+                (Some(banned_uuid), Some(uuid)) => *banned_uuid == uuid,
+                _ => banned_name.eq_ignore_ascii_case(name)
+            })
+            .map(|(_, _, ban)| ban)
+    }
+
+    pub fn len(&self) -> usize {
+        self.ips.len() + self.players.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ips.is_empty() && self.players.is_empty()
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0)
+}
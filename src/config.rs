@@ -0,0 +1,467 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::packet::{DEFAULT_MAX_NESTING_DEPTH, PacketType};
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // every address gets its own listener, e.g. both 0.0.0.0:25565 and [::]:25565
+    pub bind: Vec<SocketAddr>,
+    // also listen on a unix socket at this path, for a proxy in front of the server on the same host
+    pub unix_socket: Option<PathBuf>,
+    // accept handshakes with protocol versions that aren't supported
+    pub experimental_protocols: bool,
+    pub difficulty: Difficulty,
+    pub difficulty_locked: bool,
+    // disconnect a client that produces more decoding errors than this within protocol_error_window
+    pub max_protocol_errors: u32,
+    #[serde(deserialize_with = "deserialize_seconds")]
+    pub protocol_error_window: Duration,
+    // chat component JSON shown as the server list description
+    pub description: String,
+    pub maintenance: Maintenance,
+    // only players listed in this file, in the format of the vanilla whitelist.json, can join
+    pub whitelist: Option<PathBuf>,
+    // banned addresses and players, as {"ips": [{"ip", "reason", "expires"}], "players": [{"name",
+    // "uuid", "reason", "expires"}]} where only ip and name are required
+    pub bans: Option<PathBuf>,
+    // server brand sent to clients on the minecraft:brand channel, shown in the debug screen
+    pub brand: String,
+    // disconnect a client that doesn't accept outgoing data within this time
+    #[serde(deserialize_with = "deserialize_seconds")]
+    pub write_timeout: Duration,
+    // disconnect a client that didn't complete the handshake within this time of connecting
+    #[serde(deserialize_with = "deserialize_seconds")]
+    pub handshake_timeout: Duration,
+    // disconnect a client that sent nothing for this long after the handshake
+    #[serde(deserialize_with = "deserialize_seconds")]
+    pub idle_timeout: Duration,
+    // idle time before the OS starts probing a client connection that went away silently, and the
+    // time between probes, disabled when zero
+    #[serde(deserialize_with = "deserialize_seconds")]
+    pub tcp_keepalive: Duration,
+    // limit for nested optional/compound structures in a single packet
+    pub max_nesting_depth: usize,
+    pub world_flags: ClientWorldFlags,
+    // what to do when a handler leaves bytes of a packet unread
+    pub trailing_bytes: TrailingBytesPolicy,
+    pub max_players: u32,
+    // address of the UDP query listener, disabled when not set
+    pub query_address: Option<SocketAddr>,
+    // forward connections to the backend of the hostname they connected with, or to the
+    // default backend, instead of handling them locally. hostnames may be wildcards like
+    // *.example.com, the routes are reloaded on SIGHUP
+    pub routes: HashMap<String, SocketAddr>,
+    pub backend: Option<SocketAddr>,
+    // connections open at the same time, further connections wait to be accepted
+    pub max_connections: usize,
+    // connections accepted from a single address, further ones are closed right away
+    pub connection_rate_limit: ConnectionRateLimit,
+    // send the backend a PROXY protocol v2 header with the client's address before the handshake
+    pub proxy_protocol: bool,
+    // connections to the backend open at the same time, further clients are turned away
+    pub max_backend_connections: usize,
+    // answer status pings with the local description instead of forwarding them, so only logins
+    // reach the backend
+    pub answer_status_locally: bool,
+    // trust client address and uuid appended to the handshake host by a BungeeCord proxy
+    pub bungeecord_forwarding: bool,
+    // packets larger than this many bytes are compressed after login, negative disables compression
+    pub compression_threshold: i32,
+    // encrypt the connection and authenticate players with the Mojang session server
+    pub online_mode: bool,
+    // connections are dropped while the bytes buffered by all of them exceed this
+    pub max_buffered_bytes: usize,
+    // a client is disconnected when it sends more than this many bytes without completing a packet
+    pub max_packet_buffer: usize,
+    // testing aid that delays every write to the client
+    pub simulated_latency: SimulatedLatency,
+    // acknowledgements and similar packets that are accepted without being handled
+    pub ignored_packets: HashSet<PacketType>,
+    // disconnect clients sending packets that aren't handled instead of skipping them, for testing
+    pub strict_packets: bool,
+    // 64x64 png shown in the server list, left out when the file doesn't exist
+    pub favicon: PathBuf,
+    // answer the server list ping of clients from before 1.7
+    pub legacy_ping: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind: vec![SocketAddr::from(([127, 0, 0, 1], 25565))],
+            unix_socket: None,
+            experimental_protocols: false,
+            difficulty: Difficulty::Normal,
+            difficulty_locked: false,
+            max_protocol_errors: 10,
+            protocol_error_window: Duration::from_secs(10),
+            description: r#"{"text": "Hello world"}"#.to_string(),
+            maintenance: Maintenance::default(),
+            whitelist: None,
+            bans: None,
+            brand: "funny-proxy".to_string(),
+            write_timeout: Duration::from_secs(10),
+            handshake_timeout: Duration::from_secs(10),
+            idle_timeout: Duration::from_secs(60),
+            tcp_keepalive: Duration::ZERO,
+            max_nesting_depth: DEFAULT_MAX_NESTING_DEPTH,
+            world_flags: ClientWorldFlags::default(),
+            trailing_bytes: TrailingBytesPolicy::Ignore,
+            max_players: 100,
+            query_address: None,
+            routes: HashMap::new(),
+            backend: None,
+            max_connections: 1024,
+            connection_rate_limit: ConnectionRateLimit::default(),
+            proxy_protocol: false,
+            max_backend_connections: 256,
+            answer_status_locally: false,
+            bungeecord_forwarding: false,
+            compression_threshold: 256,
+            online_mode: false,
+            max_buffered_bytes: 64 * 1024 * 1024,
+            max_packet_buffer: 2 * 1024 * 1024,
+            simulated_latency: SimulatedLatency::default(),
+            ignored_packets: HashSet::from([
+                PacketType::PlayServerboundMessageAcknowledgment,
+            ]),
+            strict_packets: false,
+            favicon: PathBuf::from("server-icon.png"),
+            legacy_ping: true,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrailingBytesPolicy {
+    #[default]
+    Ignore,
+    Warn,
+    Disconnect,
+}
+
+// flags sent to the client in the join game packet
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientWorldFlags {
+    pub reduced_debug_info: bool,
+    pub enable_respawn_screen: bool,
+    pub is_debug: bool,
+    pub is_flat: bool,
+}
+
+impl Default for ClientWorldFlags {
+    fn default() -> Self {
+        ClientWorldFlags {
+            reduced_debug_info: false,
+            enable_respawn_screen: true,
+            is_debug: false,
+            is_flat: false,
+        }
+    }
+}
+
+// a fixed delay plus up to jitter on top of it, disabled when both are zero
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimulatedLatency {
+    #[serde(rename = "delay_ms", deserialize_with = "deserialize_millis")]
+    pub delay: Duration,
+    #[serde(rename = "jitter_ms", deserialize_with = "deserialize_millis")]
+    pub jitter: Duration,
+}
+
+impl SimulatedLatency {
+    pub fn is_enabled(&self) -> bool {
+        !self.delay.is_zero() || !self.jitter.is_zero()
+    }
+
+    pub fn sample(&self) -> Duration {
+        if self.jitter.is_zero() {
+            return self.delay;
+        }
+
+        self.delay + self.jitter.mul_f64(rand::random::<f64>())
+    }
+}
+
+// logins of everyone but the allowed players are refused while it's enabled, which can also be
+// toggled at runtime
+#[derive(PartialEq, Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Maintenance {
+    pub enabled: bool,
+    // chat component JSON shown as the server list description instead of the usual one
+    pub motd: String,
+    // shown to refused players
+    pub message: String,
+    // names or uuids of the players that can still join
+    pub allowed: Vec<String>,
+}
+
+impl Default for Maintenance {
+    fn default() -> Self {
+        Maintenance {
+            enabled: false,
+            motd: r#"{"text": "Maintenance", "color": "red"}"#.to_string(),
+            message: "The server is under maintenance, try again later.".to_string(),
+            allowed: Vec::new(),
+        }
+    }
+}
+
+impl Maintenance {
+    pub fn allows(&self, name: &str, uuid: Option<Uuid>) -> bool {
+        self.allowed.iter().any(|allowed| match allowed.parse::<Uuid>() {
+            Ok(allowed) => Some(allowed) == uuid,
+            Err(_) => allowed.eq_ignore_ascii_case(name)
+        })
+    }
+}
+
+// up to connections per window, disabled when connections is zero
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConnectionRateLimit {
+    pub connections: u32,
+    #[serde(deserialize_with = "deserialize_seconds")]
+    pub window: Duration,
+}
+
+impl Default for ConnectionRateLimit {
+    fn default() -> Self {
+        ConnectionRateLimit {
+            connections: 10,
+            window: Duration::from_secs(10),
+        }
+    }
+}
+
+impl ConnectionRateLimit {
+    pub fn is_enabled(&self) -> bool {
+        self.connections > 0
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    InvalidDescription(String),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(e) => Display::fmt(e, f),
+            ConfigError::Parse(e) => Display::fmt(e, f),
+            ConfigError::InvalidDescription(e) => write!(f, "invalid description: {}", e)
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl Config {
+    // reads the config from a TOML file, falling back to the defaults when it doesn't exist
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, ConfigError> {
+        let config = match std::fs::read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents).map_err(ConfigError::Parse)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Config::default(),
+            Err(e) => return Err(ConfigError::Io(e))
+        };
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let description: Value = serde_json::from_str(&self.description)
+            .map_err(|e| ConfigError::InvalidDescription(e.to_string()))?;
+
+        if !is_chat_component(&description) {
+            return Err(ConfigError::InvalidDescription("not a chat component".to_string()));
+        }
+
+        let motd: Value = serde_json::from_str(&self.maintenance.motd)
+            .map_err(|e| ConfigError::InvalidDescription(format!("maintenance motd: {}", e)))?;
+
+        if !is_chat_component(&motd) {
+            return Err(ConfigError::InvalidDescription("maintenance motd is not a chat component".to_string()));
+        }
+
+        Ok(())
+    }
+
+    // the description with all formatting stripped, for places that can't show components
+    pub fn description_text(&self) -> String {
+        let mut text = String::new();
+
+        if let Ok(description) = serde_json::from_str::<Value>(&self.description) {
+            append_component_text(&description, &mut text);
+        }
+
+        text
+    }
+}
+
+const COMPONENT_CONTENT_KEYS: &[&str] = &["text", "translate", "keybind", "score", "selector", "nbt"];
+
+fn is_chat_component(value: &Value) -> bool {
+    match value {
+        Value::String(_) => true,
+        Value::Array(parts) => !parts.is_empty() && parts.iter().all(is_chat_component),
+        Value::Object(fields) => {
+            if !COMPONENT_CONTENT_KEYS.iter().any(|key| fields.contains_key(*key)) {
+                return false;
+            }
+
+            match fields.get("extra") {
+                None => true,
+                Some(Value::Array(extra)) => extra.iter().all(is_chat_component),
+                Some(_) => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Difficulty {
+    Peaceful = 0,
+    Easy = 1,
+    #[default]
+    Normal = 2,
+    Hard = 3,
+}
+
+fn append_component_text(value: &Value, out: &mut String) {
+    match value {
+        Value::String(text) => out.push_str(text),
+        Value::Array(parts) => parts.iter().for_each(|part| append_component_text(part, out)),
+        Value::Object(fields) => {
+            if let Some(Value::String(text)) = fields.get("text") {
+                out.push_str(text);
+            }
+
+            if let Some(Value::Array(extra)) = fields.get("extra") {
+                extra.iter().for_each(|part| append_component_text(part, out));
+            }
+        }
+        _ => {}
+    }
+}
+
+fn deserialize_seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    let seconds = f64::deserialize(deserializer)?;
+
+    Duration::try_from_secs_f64(seconds).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_millis<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    Ok(Duration::from_millis(u64::deserialize(deserializer)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // loads the config from a file of its own, named after the test
+    fn load(name: &str, contents: &str) -> Result<Config, ConfigError> {
+        let path = std::env::temp_dir().join(format!("funny-proxy-{}-{}.toml", name, std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+
+        let config = Config::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        config
+    }
+
+    #[test]
+    fn invalid_descriptions_are_rejected_at_load() {
+        let invalid = [
+            r#"description = "not json""#,
+            r#"description = '{"color": "red"}'"#,
+            r#"description = '{"text": "a", "extra": {"text": "b"}}'"#,
+            r#"description = '[]'"#,
+        ];
+
+        for (i, contents) in invalid.iter().enumerate() {
+            assert!(matches!(load(&format!("invalid-description-{}", i), contents), Err(ConfigError::InvalidDescription(_))), "{}", contents);
+        }
+    }
+
+    #[test]
+    fn component_descriptions_are_accepted() {
+        let config = load("component-description", r#"description = '{"text": "Hello ", "color": "gold", "extra": [{"text": "world", "bold": true}, "!"]}'"#).unwrap();
+
+        assert_eq!(config.description_text(), "Hello world!");
+    }
+
+    #[test]
+    fn missing_files_load_the_defaults() {
+        let config = Config::load(std::env::temp_dir().join("funny-proxy-does-not-exist.toml")).unwrap();
+
+        assert_eq!(config.bind, Config::default().bind);
+        assert_eq!(config.max_players, Config::default().max_players);
+        assert_eq!(config.backend, None);
+    }
+
+    #[test]
+    fn settings_are_read_from_the_file() {
+        let config = load("settings", r#"
+            bind = ["0.0.0.0:25566", "[::]:25566"]
+            backend = "10.0.0.2:25565"
+            max_players = 7
+            description = '{"text": "hi"}'
+            write_timeout = 2.5
+        "#).unwrap();
+
+        assert_eq!(config.bind, ["0.0.0.0:25566".parse().unwrap(), "[::]:25566".parse::<SocketAddr>().unwrap()]);
+        assert_eq!(config.backend, Some("10.0.0.2:25565".parse().unwrap()));
+        assert_eq!(config.max_players, 7);
+        assert_eq!(config.description_text(), "hi");
+        assert_eq!(config.write_timeout, Duration::from_millis(2500));
+        // everything left out keeps its default
+        assert_eq!(config.idle_timeout, Config::default().idle_timeout);
+    }
+
+    #[test]
+    fn invalid_addresses_and_unknown_settings_are_rejected() {
+        assert!(matches!(load("invalid-address", r#"bind = ["localhost"]"#), Err(ConfigError::Parse(_))));
+        assert!(matches!(load("unknown-setting", "max_player = 7"), Err(ConfigError::Parse(_))));
+    }
+
+    #[test]
+    fn latency_samples_stay_within_the_jitter() {
+        let latency = SimulatedLatency { delay: Duration::from_millis(100), jitter: Duration::from_millis(20) };
+
+        for _ in 0..1000 {
+            let sample = latency.sample();
+            assert!(sample >= latency.delay && sample <= latency.delay + latency.jitter, "{:?}", sample);
+        }
+
+        assert!(latency.is_enabled());
+        assert!(!SimulatedLatency::default().is_enabled());
+        assert_eq!(SimulatedLatency { delay: Duration::from_millis(5), jitter: Duration::ZERO }.sample(), Duration::from_millis(5));
+    }
+
+    #[test]
+    fn latency_is_configured_in_milliseconds() {
+        let config = load("latency", "simulated_latency = { delay_ms = 250, jitter_ms = 50 }").unwrap();
+
+        assert_eq!(config.simulated_latency.delay, Duration::from_millis(250));
+        assert_eq!(config.simulated_latency.jitter, Duration::from_millis(50));
+    }
+}
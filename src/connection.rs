@@ -1,69 +1,282 @@
+use std::collections::VecDeque;
 use std::error::Error;
-use std::io::Write;
+use std::future::Future;
+use std::io::{ErrorKind, Write};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
-use base64::Engine;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
 use tokio::net::TcpStream;
+use tokio::sync::{mpsc, Notify, watch};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, field, info, info_span, Instrument, Span, trace, warn};
+use tokio::sync::OwnedSemaphorePermit;
 use uuid::Uuid;
 
+use crate::auth::{has_joined, offline_uuid, ProfileProperty, server_hash};
+use crate::config::{Config, TrailingBytesPolicy};
+use crate::encryption::{EncryptionError, generate_verify_token, StreamCipher};
+use crate::packet::payload::{Decodable, Handshake, LoginStart, StatusPing, StatusRequest};
+use crate::packet::player_info::{ADD_PLAYER, PlayerInfoEntry, UPDATE_LISTED};
+use crate::protocol::ProtocolVersion;
+use crate::server::{OnlinePlayer, Server};
+use crate::status::{build_pong, build_status_response, Players, StatusResponse, Version};
 use crate::connection::ConnectionState::Disconnected;
-use crate::packet::{DecodingError, Packet, PacketReader, PacketType, PacketWriter, write_var_int};
+use crate::keep_alive::KeepAlive;
+use crate::registry::registry_codec;
+use crate::outbound::{OUTBOUND_QUEUE_SIZE, Outbound, PacketSender, write_outbound};
+use crate::middleware::{forward_filtered, PacketMiddleware, ProxiedProtocol};
+use crate::forwarding::{ForwardedClient, has_forwarding_data, parse_bungeecord_host, write_proxy_protocol_header};
+use crate::stream::ClientStream;
+use crate::packet::{DecodingError, Direction, hex_dump, MAX_COMMAND_ARGUMENT_SIGNATURES, MESSAGE_SIGNATURE_LENGTH, Packet, PacketReader, PacketType, PacketWriter, SpawnPosition};
 
 static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(0);
 
+// parsed bytes at the start of the buffer that are worth moving the rest for
+const COMPACT_THRESHOLD: usize = 4096;
+
+// chunks sent in every direction of the spawn chunk
+const SPAWN_CHUNK_RADIUS: i32 = 1;
+
+// plugin message channels handled by the server itself
+const BRAND_CHANNEL: &str = "minecraft:brand";
+const REGISTER_CHANNEL: &str = "minecraft:register";
+
+// first byte of the server list ping of old clients
+const LEGACY_PING: u8 = 0xFE;
+
 #[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
 pub enum ConnectionState {
     Handshake,
     Status,
     Login,
     Play,
+    Proxying,
     Disconnected,
 }
 
-pub struct Connection {
+#[derive(Clone, Debug)]
+pub struct ConnectionStats {
+    pub packets_received: u64,
+    pub bytes_received: u64,
+    pub duration: Duration,
+}
+
+pub type HookFuture<'a> = Pin<Box<dyn Future<Output = ()> + Send + 'a>>;
+
+pub trait ConnectionHooks: Send + Sync {
+    fn on_connect(&self, _id: u64, _peer: SocketAddr) -> HookFuture<'_> {
+        Box::pin(async {})
+    }
+
+    fn on_disconnect(&self, _id: u64, _peer: SocketAddr, _reason: String, _stats: ConnectionStats) -> HookFuture<'_> {
+        Box::pin(async {})
+    }
+}
+
+// a client connection over any stream, which is a ClientStream from the listeners
+pub struct Connection<S = ClientStream> {
     id: u64,
-    stream: TcpStream,
+    peer: SocketAddr,
+    // how the client is logged, which is the socket path rather than an address for unix sockets
+    peer_name: String,
+    local: SocketAddr,
+    stream: ReadHalf<S>,
+    outbound: mpsc::Sender<Outbound>,
+    writer: Option<JoinHandle<Result<WriteHalf<S>, ConnectionError>>>,
     temp_buffer: Vec<u8>,
     current_packet: Vec<u8>,
+    // the bytes of current_packet before this were already parsed
+    read_offset: usize,
     state: ConnectionState,
+    server: Arc<Server>,
+    config: Arc<Config>,
+    protocol_errors: u32,
+    protocol_errors_since: Instant,
+    hooks: Option<Arc<dyn ConnectionHooks>>,
+    middleware: Option<Arc<dyn PacketMiddleware>>,
+    connected_at: Instant,
+    packets_received: u64,
+    bytes_received: u64,
+    username: Option<String>,
+    // the backend, and the state the client asked it for
+    upstream: Option<(TcpStream, ConnectionState)>,
+    backend_permit: Option<OwnedSemaphorePermit>,
+    forwarded: Option<ForwardedClient>,
+    compression_threshold: Option<usize>,
+    verify_token: Option<[u8; 4]>,
+    cipher: Option<StreamCipher>,
+    // notified when this connection holds the most of the buffers that exceed max_buffered_bytes
+    buffer_eviction: Arc<Notify>,
+    keep_alive: Option<Arc<KeepAlive>>,
+    // negotiated in the handshake, selects the packet ids
+    protocol: ProtocolVersion,
+    shutdown: watch::Receiver<bool>,
+    position: (f64, f64, f64),
+    // yaw and pitch
+    rotation: (f32, f32),
+    next_teleport_id: i32,
+    // the teleports the client has yet to confirm, oldest first
+    pending_teleports: VecDeque<i32>,
+    // the play ping the client has yet to answer
+    pending_ping: Option<i32>,
 }
 
 #[derive(Debug)]
-enum ConnectionError {
+pub enum ConnectionError {
     EndOfStream,
+    WriteTimeout,
+    KeepAliveTimeout,
+    ShuttingDown,
+    PacketBufferFull,
+    // chosen to free its buffer to get all of them back under max_buffered_bytes
+    OutOfBufferSpace,
+    ReadTimeout,
+    // the client sent something that isn't valid protocol
+    Decode(DecodingError),
     Other(Box<dyn Error + Send + Sync>),
 }
 
-impl Connection {
+impl From<DecodingError> for ConnectionError {
+    fn from(e: DecodingError) -> Self {
+        ConnectionError::Decode(e)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> Connection<S> {
+    // handles the connection until it closes, logging within a span that identifies it
     pub async fn process(&mut self) {
-        self.log("connected");
+        let span = info_span!("connection", id = self.id, peer = %self.peer_name, username = field::Empty);
+        self.run().instrument(span).await
+    }
+
+    async fn run(&mut self) {
+        info!("connected");
+
+        if let Some(hooks) = self.hooks.clone() {
+            hooks.on_connect(self.id, self.peer).await;
+        }
 
         loop {
             match self.try_read().await {
                 Ok(()) => {}
-                Err(e) => {
-                    let reason = format!("connection error: {:?}", e).to_string();
-                    self.disconnect(&reason).await;
+                Err(ConnectionError::WriteTimeout) => {
+                    self.disconnect("write timeout").await;
+                    break;
+                }
+                Err(ConnectionError::KeepAliveTimeout) => {
+                    self.disconnect("timed out").await;
+                    break;
+                }
+                Err(ConnectionError::ShuttingDown) => {
+                    self.disconnect("server shutting down").await;
+                    break;
+                }
+                Err(ConnectionError::ReadTimeout) => {
+                    self.disconnect("read timeout").await;
+                    break;
+                }
+                Err(ConnectionError::PacketBufferFull) => {
+                    self.disconnect("packet too large").await;
+                    break;
+                }
+                Err(ConnectionError::OutOfBufferSpace) => {
+                    self.disconnect("server is out of buffer space").await;
+                    break;
+                }
+                // the client quit, nothing is left to tell it
+                Err(ConnectionError::EndOfStream) => {
+                    if !self.unparsed().is_empty() {
+                        debug!("dropping {} bytes of an incomplete packet", self.unparsed().len());
+                    }
+
+                    if let Some(writer) = self.writer.take() {
+                        writer.abort();
+                    }
+
+                    self.close("end of stream").await;
+                    break;
+                }
+                // the client's fault, which is only worth a closer look when debugging it
+                Err(ConnectionError::Decode(e)) => {
+                    debug!("protocol error: {:?}", e);
+                    self.disconnect(&format!("protocol error: {}", e)).await;
+                    break;
+                }
+                Err(ConnectionError::Other(e)) => {
+                    warn!("connection error: {}", e);
+                    self.disconnect(&format!("connection error: {}", e)).await;
                     break;
                 }
             }
+
+            if self.state == ConnectionState::Proxying {
+                self.proxy().await;
+                break;
+            }
+
+            // a handler turned the client away
+            if self.state == Disconnected {
+                break;
+            }
         }
 
-        self.log("disconnected");
+        info!("disconnected");
     }
 
-    async fn try_read(&mut self) -> Result<(), ConnectionError> {
-        if let Err(e) = self.stream.readable().await {
-            return Err(ConnectionError::Other(e.into()));
+    // the whole handshake has to arrive within handshake_timeout of connecting, after that the
+    // client only has to send something every idle_timeout
+    fn read_deadline(&self) -> tokio::time::Instant {
+        match self.state {
+            ConnectionState::Handshake => tokio::time::Instant::from_std(self.connected_at + self.config.handshake_timeout),
+            _ => tokio::time::Instant::now() + self.config.idle_timeout
         }
+    }
+
+    async fn try_read(&mut self) -> Result<(), ConnectionError> {
+        let deadline = self.read_deadline();
+        let writer = self.writer.as_mut().expect("reading after the writer stopped");
+        let keep_alive = self.keep_alive.clone();
+        let buffer_eviction = self.buffer_eviction.clone();
+        let keep_alive_timed_out = async move {
+            match keep_alive {
+                Some(keep_alive) => keep_alive.timed_out().await,
+                None => std::future::pending().await
+            }
+        };
+
+        let read = tokio::select! {
+            // read_buf waits for data by itself and reads 0 bytes at the end of the stream
+            read = tokio::time::timeout_at(deadline, self.stream.read_buf(&mut self.temp_buffer)) => {
+                read.map_err(|_| ConnectionError::ReadTimeout)?
+            }
+            _ = keep_alive_timed_out => return Err(ConnectionError::KeepAliveTimeout),
+            _ = buffer_eviction.notified() => return Err(ConnectionError::OutOfBufferSpace),
+            _ = self.shutdown.wait_for(|shutting_down| *shutting_down) => return Err(ConnectionError::ShuttingDown),
+            // the writer only stops by itself when writing failed
+            finished = writer => {
+                self.writer = None;
+                return Err(match finished {
+                    Ok(Ok(_)) => ConnectionError::Other("writer stopped".into()),
+                    Ok(Err(e)) => e,
+                    Err(e) => ConnectionError::Other(e.into())
+                });
+            }
+        };
 
-        match self.stream.read_buf(&mut self.temp_buffer).await {
+        match read {
             Ok(0) => {
                 Err(ConnectionError::EndOfStream)
             }
-            Ok(_n) => {
-                self.data_read().await
+            Ok(n) => {
+                self.bytes_received += n as u64;
+
+                let result = self.data_read().await;
+                self.account_buffered();
+                result
             }
             Err(e) => {
                 Err(ConnectionError::Other(e.into()))
@@ -76,16 +289,30 @@ impl Connection {
             return Ok(());
         }
 
+        if let Some(cipher) = &mut self.cipher {
+            cipher.decrypt(&mut self.temp_buffer);
+        }
+
         self.current_packet.append(&mut self.temp_buffer);
         self.temp_buffer.clear();
 
+        // backstop for many connections slowly filling their buffers at once, those holding the
+        // most have to go, which may include this one
+        if self.account_buffered() > self.config.max_buffered_bytes {
+            let evicted = self.server.evict_largest_buffers(self.config.max_buffered_bytes);
+            warn!("buffers exceed {} bytes, dropping the {} connections holding the most", self.config.max_buffered_bytes, evicted);
+        }
+
         loop {
-            if self.state == Disconnected {
+            if self.state == Disconnected || self.state == ConnectionState::Proxying {
                 return Ok(());
             }
 
             match self.try_to_parse_packet().await {
                 Ok(true) => {}
+                Ok(false) if self.unparsed().len() > self.config.max_packet_buffer => {
+                    return Err(ConnectionError::PacketBufferFull);
+                }
                 Ok(false) => {
                     return Ok(());
                 }
@@ -95,34 +322,210 @@ impl Connection {
     }
 
     async fn try_to_parse_packet(&mut self) -> Result<bool, ConnectionError> {
-        match Packet::decode(&self.current_packet, self.state).await {
+        // a frame can't be that long, so this is the ping of a client from before 1.7
+        if self.state == ConnectionState::Handshake && self.config.legacy_ping && self.unparsed().first() == Some(&LEGACY_PING) {
+            self.handle_legacy_ping().await?;
+            return Ok(false);
+        }
+
+        // the length of the next frame may not have arrived completely yet
+        if PacketReader::create(self.unparsed()).peek_varint()?.is_none() {
+            return Ok(false);
+        }
+
+        match Packet::decode(self.unparsed(), self.state, self.protocol, self.compression_threshold).await {
             Ok(packet) => {
-                self.current_packet.drain(0..packet.raw_size);
+                self.consume(packet.raw_size);
+                self.packets_received += 1;
+
+                #[cfg(debug_assertions)]
+                self.check_frame_alignment();
+
                 self.handle_packet(packet).await?;
 
                 Ok(true)
             }
             Err(DecodingError::PacketTooSmall) => Ok(false),
-            Err(e) => Err(ConnectionError::Other(e.into()))
+            // clients send plenty of packets that aren't handled, which isn't an error
+            Err(DecodingError::InvalidPacketId(id, state)) if !self.config.strict_packets => {
+                let frame_size = Packet::frame_size(self.unparsed())?;
+                self.consume(frame_size);
+                debug!("skipping unknown packet {:#04x} in state {:?}", id, state);
+
+                Ok(true)
+            }
+            Err(e @ DecodingError::InvalidPacketId(..)) => Err(e.into()),
+            Err(e) => {
+                // the frame itself is intact, so the malformed packet can be skipped
+                let frame_size = match Packet::frame_size(self.unparsed()) {
+                    Ok(size) => size,
+                    Err(_) => return Err(ConnectionError::Decode(e))
+                };
+
+                self.consume(frame_size);
+                debug!("skipping malformed packet: {}", e);
+
+                if self.record_protocol_error() {
+                    self.disconnect("Too many protocol errors.").await;
+                }
+
+                Ok(true)
+            }
+        }
+    }
+
+    fn unparsed(&self) -> &[u8] {
+        &self.current_packet[self.read_offset..]
+    }
+
+    // skips parsed bytes, the buffer is only compacted once they add up or nothing is left, which
+    // keeps many small packets in one read from moving the rest of the buffer every time. the
+    // parsed bytes also have to outweigh the rest, so every byte is moved a bounded number of times
+    fn consume(&mut self, bytes: usize) {
+        self.read_offset += bytes;
+
+        if self.read_offset == self.current_packet.len() {
+            self.discard_unparsed();
+        } else if self.read_offset > COMPACT_THRESHOLD && self.read_offset >= self.unparsed().len() {
+            self.current_packet.drain(0..self.read_offset);
+            self.read_offset = 0;
+        }
+    }
+
+    fn discard_unparsed(&mut self) {
+        self.current_packet.clear();
+        self.read_offset = 0;
+    }
+
+    // the buffer must start at the length of the next frame, otherwise the cursor was advanced wrongly
+    #[cfg(debug_assertions)]
+    fn check_frame_alignment(&self) {
+        if self.unparsed().is_empty() {
+            return;
+        }
+
+        let mut reader = PacketReader::create(self.unparsed());
+        match reader.read_varint() {
+            Ok(length) if length > 0 && length <= crate::packet::MAX_PACKET_LENGTH => {}
+            Err(DecodingError::PacketTooSmall) => {}
+            other => error!("buffer misaligned after packet, next frame length is {:?}", other)
+        }
+    }
+
+    // answers with a kick packet holding the server list information in place of the reason
+    async fn handle_legacy_ping(&mut self) -> Result<(), ConnectionError> {
+        debug!("received legacy ping");
+
+        let response = [
+            "\u{a7}1".to_string(),
+            "127".to_string(), // protocol, never compatible so clients show the version name
+            ProtocolVersion::LATEST_SUPPORTED.name(),
+            self.config.description_text(),
+            self.server.online_players().to_string(),
+            self.config.max_players.to_string(),
+        ].join("\0");
+
+        let encoded: Vec<u16> = response.encode_utf16().collect();
+        let mut packet = Vec::with_capacity(3 + encoded.len() * 2);
+        packet.push(0xFF);
+        packet.extend_from_slice(&(encoded.len() as u16).to_be_bytes());
+        encoded.iter().for_each(|unit| packet.extend_from_slice(&unit.to_be_bytes()));
+
+        self.queue(Outbound::Raw(packet)).await?;
+        self.discard_unparsed();
+        self.state = ConnectionState::Status;
+        self.disconnect("legacy ping").await;
+
+        Ok(())
+    }
+
+    // updates this connection's share of the bytes buffered by all connections, returning the total
+    fn account_buffered(&mut self) -> usize {
+        self.server.set_buffered(self.id, self.current_packet.len())
+    }
+
+    // supported clients are shown as compatible, the others get the version they should use
+    fn status_version(&self) -> ProtocolVersion {
+        if self.protocol.is_supported() { self.protocol } else { ProtocolVersion::LATEST_SUPPORTED }
+    }
+
+    fn record_protocol_error(&mut self) -> bool {
+        let now = Instant::now();
+
+        if now.duration_since(self.protocol_errors_since) > self.config.protocol_error_window {
+            self.protocol_errors = 0;
+            self.protocol_errors_since = now;
         }
+
+        self.protocol_errors += 1;
+        self.protocol_errors > self.config.max_protocol_errors
     }
 
     async fn handle_packet(&mut self, packet: Packet) -> Result<(), ConnectionError> {
-        self.log(format!("received packet of type: {:?} and length {}", packet.packet_type, packet.data.len()));
+        debug!("received packet of type: {:?} and length {}", packet.packet_type, packet.data.len());
+        // only formatted when tracing is enabled, e.g. with RUST_LOG=funny_proxy::connection=trace
+        trace!("packet data:\n{}", hex_dump(&packet.data));
 
         let mut reader = PacketReader::create(&packet.data);
+        reader.set_max_depth(self.config.max_nesting_depth);
 
         match packet.packet_type {
             PacketType::HandshakeServerboundStart => {
-                let protocol_version = reader.read_varint().unwrap();
-                let host = reader.read_string(255).unwrap();
-                let port = reader.read_short().unwrap();
-                let next_state = reader.read_varint().unwrap();
+                let Handshake { protocol_version, host, port, next_state } = Handshake::decode(&mut reader)?;
 
-                self.log(format!(
+                // only forwarding data makes the host longer than a host name
+                if !self.config.bungeecord_forwarding && host.chars().count() > 255 {
+                    return Err(DecodingError::StringTooLarge.into());
+                }
+
+                info!(
                     "client connected with protocol = {}, hostname = {}:{}, next_state = {}",
-                    protocol_version, host, port, next_state
-                ));
+                    protocol_version, host.split('\0').next().unwrap_or_default(), port, next_state
+                );
+
+                if self.config.bungeecord_forwarding {
+                    match parse_bungeecord_host(&host) {
+                        Ok((_, client)) => {
+                            info!("forwarded from {} with uuid {}", client.address, client.uuid);
+
+                            // the proxy doesn't forward the client's port
+                            self.peer = SocketAddr::new(client.address, 0);
+                            Span::current().record("peer", field::display(self.peer));
+                            self.forwarded = Some(client);
+                        }
+                        Err(e) => {
+                            self.disconnect(&format!("invalid forwarding data: {}", e)).await;
+                            return Ok(());
+                        }
+                    }
+                } else if has_forwarding_data(&host) {
+                    self.disconnect("received forwarding data but forwarding is disabled").await;
+                    return Ok(());
+                }
+
+                // only logins are gated, status pings of other versions are answered so that the
+                // server list shows which version to use
+                self.protocol = ProtocolVersion::from_i32(protocol_version);
+                if !self.protocol.is_supported() && next_state == 2 {
+                    if !self.config.experimental_protocols {
+                        let reason = format!("Unsupported protocol version {}, please use {}.", protocol_version, ProtocolVersion::LATEST_SUPPORTED.name());
+                        return self.refuse(next_state, &reason).await;
+                    }
+
+                    warn!("protocol {} is not supported, continuing anyway", protocol_version);
+                }
+
+                let router = self.server.router();
+                if !router.is_empty() {
+                    return match router.route(&host) {
+                        Some(_) if next_state == 1 && self.config.answer_status_locally => {
+                            self.state = ConnectionState::Status;
+                            Ok(())
+                        }
+                        Some(backend) => self.connect_backend(backend, next_state, &packet).await,
+                        None => self.refuse(next_state, &format!("no route for {}", host)).await
+                    };
+                }
 
                 match next_state {
                     1 => self.state = ConnectionState::Status,
@@ -131,134 +534,1936 @@ impl Connection {
                 }
             }
             PacketType::StatusServerboundRequest => {
-                let mut packet = PacketWriter::create(1024);
-                packet.write_packet_type(PacketType::StatusClientboundResponse);
-                packet.write_string(r#"{
-    "version": {
-        "name": "1.19.4",
-        "protocol": 762
-    },
-    "players": {
-        "max": 100,
-        "online": 5,
-        "sample": []
-    },
-    "description": {
-        "text": "Hello world"
-    }
-}"#);
-
-                self.send_packet(&packet).await;
+                StatusRequest::decode(&mut reader)?;
+
+                let description = if !self.server.is_ready() {
+                    serde_json::json!({ "text": "Starting up..." })
+                } else if self.server.in_maintenance() {
+                    serde_json::from_str(&self.config.maintenance.motd).map_err(|e| ConnectionError::Other(e.into()))?
+                } else {
+                    serde_json::from_str(&self.config.description).map_err(|e| ConnectionError::Other(e.into()))?
+                };
+
+                // no client speaks protocol -1, so they show the version name in red instead of the ping
+                let version = if self.server.in_maintenance() {
+                    Version { name: "Maintenance".to_string(), protocol: -1 }
+                } else {
+                    Version { name: self.status_version().name(), protocol: self.status_version().as_i32() }
+                };
+
+                let status = StatusResponse {
+                    version,
+                    players: Players {
+                        max: self.config.max_players as i32,
+                        online: self.server.online_players() as i32,
+                        sample: Vec::new(),
+                    },
+                    description,
+                    favicon: self.server.favicon.clone(),
+                };
+
+                let response = build_status_response(&status, self.protocol).map_err(|e| ConnectionError::Other(e.into()))?;
+                self.send_packet(response).await?;
             }
             PacketType::StatusServerboundPing => {
-                let value = reader.read_long().unwrap();
+                let ping = StatusPing::decode(&mut reader)?;
 
-                let mut packet = PacketWriter::create(1024);
-                packet.write_packet_type(PacketType::StatusClientboundPong);
-                packet.write_long(value);
-                self.send_packet(&packet).await;
+                self.send_packet(build_pong(ping.payload, self.protocol)).await?;
             }
             PacketType::LoginServerboundStart => {
-                let name = reader.read_string(16).unwrap();
-                let uuid = reader.read_optional(|reader| reader.read_uuid()).unwrap();
+                let LoginStart { name, uuid } = LoginStart::decode(&mut reader)?;
+
+                if !self.server.is_ready() {
+                    self.disconnect("The server is still starting up, try again shortly.").await;
+                    return Ok(());
+                }
+
+                self.username = Some(name.clone());
+                Span::current().record("username", &name);
+                info!("Player logging in with name {} and uuid {:?}", name, uuid);
+
+                // the proxy already authenticated the player
+                if let Some(client) = self.forwarded.clone() {
+                    return self.finish_login(Some(client.uuid), &client.properties).await;
+                }
+
+                let server = self.server.clone();
+                match server.key.get() {
+                    Some(key) => {
+                        let verify_token = generate_verify_token();
+
+                        let mut packet = PacketWriter::create(256);
+                        packet.write_packet_type(PacketType::LoginClientboundEncryptionRequest, self.protocol);
+                        packet.write_string(""); // server id
+                        packet.write_byte_array(key.public_key_der());
+                        packet.write_byte_array(&verify_token);
+
+                        self.send_packet(packet).await?;
+                        self.verify_token = Some(verify_token);
+                    }
+                    None => self.finish_login(uuid, &[]).await?
+                }
+            }
+            PacketType::LoginServerboundEncryptionResponse => {
+                // both encrypted with the server's public key
+                let shared_secret = reader.read_byte_array(256)?;
+                let verify_token = reader.read_byte_array(256)?;
+
+                let server = self.server.clone();
+                let (key, expected_token) = match (server.key.get(), self.verify_token.take()) {
+                    (Some(key), Some(token)) => (key, token),
+                    _ => {
+                        self.disconnect("unexpected encryption response").await;
+                        return Ok(());
+                    }
+                };
+
+                let encryption = key.decrypt(&verify_token).and_then(|verify_token| {
+                    if verify_token != expected_token {
+                        return Err(EncryptionError::VerifyTokenMismatch);
+                    }
+
+                    // each direction needs its own cipher state
+                    let shared_secret = key.decrypt(&shared_secret)?;
+                    Ok((StreamCipher::create(&shared_secret)?, StreamCipher::create(&shared_secret)?, shared_secret))
+                });
+
+                let shared_secret = match encryption {
+                    Ok((decryption, encryption, shared_secret)) => {
+                        self.enable_encryption(decryption, encryption).await?;
+                        shared_secret
+                    }
+                    Err(e) => {
+                        self.disconnect(&format!("encryption failed: {}", e)).await;
+                        return Ok(());
+                    }
+                };
+
+                let username = self.username.clone().unwrap_or_default();
+                let hash = server_hash("", &shared_secret, key.public_key_der());
+
+                let profile = match has_joined(&username, &hash).await {
+                    Ok(profile) => profile,
+                    Err(e) => {
+                        warn!("failed to authenticate {}: {}", username, e);
+                        self.disconnect("Failed to verify username!").await;
+                        return Ok(());
+                    }
+                };
+
+                let uuid = match profile.uuid() {
+                    Ok(uuid) => uuid,
+                    Err(e) => {
+                        self.disconnect(&format!("invalid profile: {}", e)).await;
+                        return Ok(());
+                    }
+                };
+
+                info!("authenticated as {} with uuid {}", profile.name, uuid);
+                Span::current().record("username", &profile.name);
+                self.username = Some(profile.name);
+                self.finish_login(Some(uuid), &profile.properties).await?;
+            }
+            PacketType::PlayServerboundChatCommand => {
+                let command = reader.read_string(256)?;
+                let _timestamp = reader.read_long()?;
+                let _salt = reader.read_long()?;
+
+                let signatures = reader.read_varint()? as usize;
+                if signatures > MAX_COMMAND_ARGUMENT_SIGNATURES {
+                    self.disconnect("too many command argument signatures").await;
+                    return Ok(());
+                }
+
+                let mut signature = [0u8; MESSAGE_SIGNATURE_LENGTH];
+                for _ in 0..signatures {
+                    let _argument = reader.read_string(16)?;
+                    reader.try_read_all(&mut signature)?;
+                }
+
+                let _message_count = reader.read_varint()?;
+                let mut acknowledged = [0u8; 3]; // fixed bitset of the last 20 messages
+                reader.try_read_all(&mut acknowledged)?;
+
+                info!("ran command /{}", command);
+            }
+            PacketType::PlayServerboundChatMessage => {
+                let message = reader.read_string(256)?;
+                let _timestamp = reader.read_long()?;
+                let _salt = reader.read_long()?;
 
-                self.log(format!("Player logging in with name {} and uuid {:?}", name, uuid));
+                if reader.read_boolean()? {
+                    let mut signature = [0u8; MESSAGE_SIGNATURE_LENGTH];
+                    reader.try_read_all(&mut signature)?;
+                }
+
+                let _message_count = reader.read_varint()?;
+                let mut acknowledged = [0u8; 3];
+                reader.try_read_all(&mut acknowledged)?;
 
-                let mut packet = PacketWriter::create(32);
-                packet.write_packet_type(PacketType::LoginClientboundSuccess);
-                packet.write_uuid(match uuid {
-                    Some(id) => id,
-                    None => Uuid::new_v4()
+                info!("said {}", message);
+
+                let name = self.username.clone().unwrap_or_default();
+                let chat = serde_json::json!({ "translate": "chat.type.text", "with": [name, message] }).to_string();
+                self.server.broadcast(|protocol| {
+                    let mut packet = PacketWriter::create(chat.len() + 8);
+                    packet.write_packet_type(PacketType::PlayClientboundSystemChatMessage, protocol);
+                    packet.write_string(&chat);
+                    packet.write_boolean(false); // in the chat, not the action bar
+                    packet
                 });
-                packet.write_string(&name);
-                packet.write_var_int(0);
+            }
+            PacketType::PlayServerboundClientInformation => {
+                let locale = reader.read_string(16)?;
+                let view_distance = reader.read_byte()?;
+                let _chat_mode = reader.read_varint()?;
+                let _chat_colors = reader.read_boolean()?;
+                let _skin_parts = reader.read_ubyte()?;
+                let _main_hand = reader.read_varint()?;
+                let _text_filtering = reader.read_boolean()?;
+                let _allow_server_listings = reader.read_boolean()?;
 
-                self.send_packet(&packet).await;
-                self.state = ConnectionState::Play;
+                debug!("client information: locale = {}, view distance = {}", locale, view_distance);
+            }
+            PacketType::PlayServerboundConfirmTeleportation => {
+                let teleport_id = reader.read_varint()?;
+
+                // confirmations arrive in order, so older teleports were overtaken by this one
+                if let Some(index) = self.pending_teleports.iter().position(|&id| id == teleport_id) {
+                    self.pending_teleports.drain(..=index);
+                } else {
+                    warn!("ignoring confirmation of unknown teleport {}", teleport_id);
+                }
+            }
+            PacketType::PlayServerboundPong => {
+                let id = reader.read_int()?;
 
-                // TODO: Dump actual NBT for 1.19.4
-                let nbt = base64::engine::general_purpose::STANDARD.decode("CgAACgATbWluZWNyYWZ0OmNoYXRfdHlwZQAKABhtaW5lY3JhZnQ6ZGltZW5zaW9uX3R5cGUACgAYbWluZWNyYWZ0OndvcmxkZ2VuL2Jpb21lAAA=").unwrap();
+                if self.pending_ping == Some(id) {
+                    self.pending_ping = None;
+                    debug!("client answered ping {}", id);
+                } else {
+                    warn!("ignoring pong of unknown ping {}", id);
+                }
+            }
+            PacketType::PlayServerboundSetPlayerPosition => {
+                let position = reader.read_vec3d()?;
+                let _on_ground = reader.read_boolean()?;
+
+                // movement from before the client got to the teleport destination
+                if self.pending_teleports.is_empty() {
+                    self.position = position;
+                }
+            }
+            PacketType::PlayServerboundSetPlayerPositionAndRotation => {
+                let position = reader.read_vec3d()?;
+                let rotation = (reader.read_float()?, reader.read_float()?);
+                let _on_ground = reader.read_boolean()?;
 
-                packet.reset();
-                packet.write_packet_type(PacketType::PlayClientboundLogin);
-                packet.write_int(12); // entity id
-                packet.write_boolean(false); // hardcore
-                packet.write_byte(0); // gamemode
-                packet.write_byte(0); // prev gamemode
-                packet.write_var_int(1); // dimension count
-                packet.write_string("minecraft:world"); // dimension id
-                packet.write_all(nbt.as_slice()).expect("failed to write nbt");
+                if self.pending_teleports.is_empty() {
+                    self.position = position;
+                    self.rotation = rotation;
+                }
+            }
+            PacketType::PlayServerboundSetPlayerRotation => {
+                let rotation = (reader.read_float()?, reader.read_float()?);
+                let _on_ground = reader.read_boolean()?;
 
-                packet.write_string("minecraft:world"); // spawn dimension id
-                packet.write_string("minecraft:world"); // spawn dimension name
+                if self.pending_teleports.is_empty() {
+                    self.rotation = rotation;
+                }
+            }
+            PacketType::PlayServerboundKeepAlive => {
+                let id = reader.read_long()?;
 
-                packet.write_long(0x7D42D4473EB771F9i64); // seed hash
-                packet.write_var_int(0); // max players  (ignored)
-                packet.write_var_int(10); // view distance
-                packet.write_var_int(10); // simulation distance
-                packet.write_boolean(false); // reduced debug info
-                packet.write_boolean(true); // enable respawn screen
-                packet.write_boolean(false); // is debug
-                packet.write_boolean(false); // is flat
-                packet.write_boolean(false); // has death location
+                let acknowledged = match &self.keep_alive {
+                    Some(keep_alive) => keep_alive.acknowledge(id),
+                    None => false
+                };
 
-                self.send_packet(&packet).await;
+                if !acknowledged {
+                    self.disconnect("invalid keep alive").await;
+                }
+            }
+            PacketType::PlayServerboundClickContainer => {
+                let window_id = reader.try_read_one()?;
+                let state_id = reader.read_varint()?;
+                let slot = reader.read_short()? as i16;
+                let button = reader.try_read_one()? as i8;
+                let mode = reader.read_varint()?;
 
-                packet.reset();
-                packet.write_packet_type(PacketType::PlayClientboundDifficulty);
-                packet.write_byte(2); // difficulty
-                packet.write_boolean(false); // difficulty locked
+                // the changed slots and carried item are not needed yet, the packet is already framed
+                reader.skip_remaining();
 
-                self.send_packet(&packet).await;
+                debug!(
+                    "clicked container {} (state = {}, slot = {}, button = {}, mode = {})",
+                    window_id, state_id, slot, button, mode
+                );
+            }
+            PacketType::PlayServerboundCloseContainer => {
+                let window_id = reader.try_read_one()?;
 
-                packet.reset();
-                packet.write_packet_type(PacketType::PlayClientboundAbilities);
-                packet.write_byte(0); // difficulty
-                packet.write_float(0.05); // fly speed
-                packet.write_float(0.1); // fov modifier
+                debug!("closed container {}", window_id);
+            }
+            PacketType::PlayServerboundPlayerAction => {
+                let status = reader.read_varint()?;
+                let (x, y, z) = reader.read_position()?;
+                let face = reader.try_read_one()?;
+                let sequence = reader.read_varint()?;
 
-                self.send_packet(&packet).await;
+                debug!(
+                    "player action {} at {} {} {} (face = {}, sequence = {})",
+                    status, x, y, z, face, sequence
+                );
+            }
+            PacketType::PlayServerboundUseItem => {
+                let hand = reader.read_varint()?;
+                let sequence = reader.read_varint()?;
 
-                packet.reset();
-                packet.write_packet_type(PacketType::PlayClientboundSetDefaultSpawnPosition);
-                packet.write_position(0, 100, 0); // position
-                packet.write_float(0f32); // angle
+                debug!("used item in hand {} (sequence = {})", hand, sequence);
+            }
+            PacketType::PlayServerboundPluginMessage => {
+                let channel = reader.read_string(32767)?;
+                let data = reader.read_remaining();
 
-                self.send_packet(&packet).await;
+                self.handle_plugin_message(&channel, data).await?;
+            }
+            packet_type if self.config.ignored_packets.contains(&packet_type) => reader.skip_remaining(),
+            _ if self.config.strict_packets => self.disconnect("Invalid packet").await,
+            packet_type => {
+                debug!("skipping unhandled packet of type {:?}", packet_type);
+                reader.skip_remaining();
             }
-            _ => self.disconnect("Invalid packet").await
         }
 
+        let trailing = reader.left_to_read();
+        if trailing > 0 && self.state != Disconnected && self.config.trailing_bytes != TrailingBytesPolicy::Ignore {
+            let error = DecodingError::TrailingBytes(trailing);
+            warn!("{} left in packet of type {:?}", error, packet.packet_type);
+
+            if self.config.trailing_bytes == TrailingBytesPolicy::Disconnect {
+                self.disconnect(&format!("{}", error)).await;
+            }
+        }
 
         Ok(())
     }
 
-    async fn send_packet(&mut self, packet: &PacketWriter) {
-        write_var_int(&mut self.stream, packet.len() as i32).await.expect("failed to write packet length");
-        self.stream.write_all(packet.as_ref()).await.expect("failed to write a packet");
-    }
+    // channels that aren't known are ignored, mods use their own
+    async fn handle_plugin_message(&mut self, channel: &str, data: &[u8]) -> Result<(), ConnectionError> {
+        match channel {
+            BRAND_CHANNEL => {
+                let brand = PacketReader::create(data).read_string(32767)?;
+                info!("client brand is {}", brand);
 
-    fn log<S: AsRef<str>>(&self, str: S) {
-        println!("connection {}: {}", self.id, str.as_ref());
+                let mut packet = PacketWriter::create(self.config.brand.len() + BRAND_CHANNEL.len() + 8);
+                packet.write_packet_type(PacketType::PlayClientboundPluginMessage, self.protocol);
+                packet.write_string(BRAND_CHANNEL);
+                packet.write_string(&self.config.brand);
+                self.queue(Outbound::Packet(packet)).await?;
+            }
+            // the channels a client wants to receive, separated by NUL
+            REGISTER_CHANNEL => {
+                for registered in data.split(|byte| *byte == 0).filter(|name| !name.is_empty()) {
+                    debug!("client registered channel {}", String::from_utf8_lossy(registered));
+                }
+            }
+            _ => debug!("ignoring plugin message on unknown channel {}", channel)
+        }
+
+        Ok(())
     }
 
-    pub async fn disconnect(&mut self, reason: &str) {
-        if self.state == Disconnected {
-            return;
+    // sends the login success and join burst, once the login is accepted
+    async fn finish_login(&mut self, uuid: Option<Uuid>, properties: &[ProfileProperty]) -> Result<(), ConnectionError> {
+        let name = self.username.clone().unwrap_or_default();
+
+        // checked once the uuid is known, which it only is after authentication in online mode,
+        // the one an offline client claims can't be trusted
+        let verified_uuid = if self.config.online_mode || self.forwarded.is_some() { uuid } else { None };
+
+        if let Some(ban) = self.server.bans().player_ban(&name, verified_uuid) {
+            info!("{} is banned", name);
+            let message = match &ban.reason {
+                Some(reason) => format!("You are banned from this server: {}", reason),
+                None => "You are banned from this server.".to_string()
+            };
+            self.disconnect(&message).await;
+            return Ok(());
         }
 
-        self.log(format!("disconnecting: {}", reason));
-        self.state = Disconnected;
-        self.stream.shutdown().await.expect("failed to shutdown");
+        if self.server.in_maintenance() && !self.config.maintenance.allows(&name, verified_uuid) {
+            let message = self.config.maintenance.message.clone();
+            self.disconnect(&message).await;
+            return Ok(());
+        }
+
+        if let Some(whitelist) = self.server.whitelist() {
+            if !whitelist.allows(&name, verified_uuid) {
+                info!("{} is not whitelisted", name);
+                self.disconnect("You are not whitelisted on this server.").await;
+                return Ok(());
+            }
+        }
+
+        if self.config.compression_threshold >= 0 {
+            let mut set_compression = PacketWriter::create(8);
+            set_compression.write_packet_type(PacketType::LoginClientboundSetCompression, self.protocol);
+            set_compression.write_var_int(self.config.compression_threshold);
+
+            self.send_packet(set_compression).await?;
+            self.compression_threshold = Some(self.config.compression_threshold as usize);
+            self.queue(Outbound::EnableCompression(self.config.compression_threshold as usize)).await?;
+        }
+
+        let mut packet = PacketWriter::create(32);
+        packet.write_packet_type(PacketType::LoginClientboundSuccess, self.protocol);
+        let uuid = match uuid {
+            Some(id) => id,
+            None => offline_uuid(&name)
+        };
+        packet.write_uuid(uuid);
+        packet.write_string(&name);
+        packet.write_properties(properties);
+
+        self.send_packet(packet).await?;
+        self.state = ConnectionState::Play;
+        self.server.player_joined(self.id, OnlinePlayer {
+            name: name.clone(),
+            protocol: self.protocol,
+            sender: self.packet_sender(),
+        });
+
+        let keep_alive = KeepAlive::create();
+        tokio::spawn(keep_alive.clone().run(self.packet_sender(), self.protocol));
+        self.keep_alive = Some(keep_alive);
+
+        let mut login = PacketWriter::create(256);
+        login.write_packet_type(PacketType::PlayClientboundLogin, self.protocol);
+        login.write_int(12); // entity id
+        login.write_boolean(false); // hardcore
+        login.write_ubyte(0); // gamemode
+        login.write_ubyte(0); // prev gamemode
+        login.write_var_int(1); // dimension count
+        login.write_string("minecraft:world"); // dimension id
+        login.write_all(registry_codec().as_ref()).map_err(|e| ConnectionError::Other(e.into()))?;
+
+        login.write_string("minecraft:world"); // spawn dimension id
+        login.write_string("minecraft:world"); // spawn dimension name
+
+        login.write_long(0x7D42D4473EB771F9i64); // seed hash
+        login.write_var_int(0); // max players  (ignored)
+        login.write_var_int(10); // view distance
+        login.write_var_int(10); // simulation distance
+        let flags = self.config.world_flags;
+        login.write_boolean(flags.reduced_debug_info); // reduced debug info
+        login.write_boolean(flags.enable_respawn_screen); // enable respawn screen
+        login.write_boolean(flags.is_debug); // is debug
+        login.write_boolean(flags.is_flat); // is flat
+        login.write_boolean(false); // has death location
+        if self.protocol != ProtocolVersion::V1_19_4 {
+            login.write_var_int(0); // portal cooldown
+        }
+
+        let mut difficulty = PacketWriter::create(8);
+        difficulty.write_packet_type(PacketType::PlayClientboundDifficulty, self.protocol);
+        difficulty.write_ubyte(self.config.difficulty as u8); // difficulty
+        difficulty.write_boolean(self.config.difficulty_locked); // difficulty locked
+
+        let mut abilities = PacketWriter::create(16);
+        abilities.write_packet_type(PacketType::PlayClientboundAbilities, self.protocol);
+        abilities.write_ubyte(0); // difficulty
+        abilities.write_float(0.05); // fly speed
+        abilities.write_float(0.1); // fov modifier
+
+        let mut spawn_position = PacketWriter::create(16);
+        spawn_position.write_packet_type(PacketType::PlayClientboundSetDefaultSpawnPosition, self.protocol);
+        let spawn = SpawnPosition::new(0, 100, 0, 0f32).map_err(|e| ConnectionError::Other(e.into()))?;
+        spawn_position.write_spawn_position(&spawn);
+
+        // puts the player in the tab list
+        let mut player_info = PacketWriter::create(64);
+        player_info.write_player_info_update(self.protocol, ADD_PLAYER | UPDATE_LISTED, &[PlayerInfoEntry {
+            uuid,
+            name,
+            properties: properties.to_vec(),
+            game_mode: 0,
+            listed: true,
+            latency: 0,
+        }]);
+
+        let position = (spawn.x() as f64 + 0.5, spawn.y() as f64, spawn.z() as f64 + 0.5);
+        let teleport = self.teleport(position);
+
+        let ping = self.ping();
+
+        let mut packets = vec![login, difficulty, abilities, spawn_position, teleport, player_info, ping];
+
+        // ground to stand on around the spawn chunk
+        let mut center_chunk = PacketWriter::create(8);
+        center_chunk.write_center_chunk(self.protocol, spawn.x() >> 4, spawn.z() >> 4);
+        packets.push(center_chunk);
+
+        for x in (spawn.x() >> 4) - SPAWN_CHUNK_RADIUS..=(spawn.x() >> 4) + SPAWN_CHUNK_RADIUS {
+            for z in (spawn.z() >> 4) - SPAWN_CHUNK_RADIUS..=(spawn.z() >> 4) + SPAWN_CHUNK_RADIUS {
+                let mut chunk = PacketWriter::create(512);
+                chunk.write_flat_chunk(self.protocol, x, z);
+                packets.push(chunk);
+            }
+        }
+
+        self.queue(Outbound::Batch(packets)).await
     }
 
-    pub fn create(stream: TcpStream) -> Connection {
-        Connection {
-            id: NEXT_CONNECTION_ID.fetch_add(1, Ordering::SeqCst),
-            stream,
-            temp_buffer: Vec::with_capacity(4096),
-            current_packet: Vec::with_capacity(4096),
-            state: ConnectionState::Handshake,
+    // moves the player, which ignores its own movement until it confirmed the teleport id
+    fn teleport(&mut self, position: (f64, f64, f64)) -> PacketWriter {
+        let teleport_id = self.next_teleport_id;
+        self.next_teleport_id = self.next_teleport_id.wrapping_add(1);
+        self.pending_teleports.push_back(teleport_id);
+        self.position = position;
+        self.rotation = (0.0, 0.0);
+
+        let mut packet = PacketWriter::create(40);
+        packet.write_packet_type(PacketType::PlayClientboundSynchronizePlayerPosition, self.protocol);
+        packet.write_vec3d(position.0, position.1, position.2);
+        packet.write_float(self.rotation.0);
+        packet.write_float(self.rotation.1);
+        packet.write_ubyte(0); // flags, every field is absolute
+        packet.write_var_int(teleport_id);
+        packet
+    }
+
+    // asks the client to echo an id, which shows that it got through everything sent before
+    fn ping(&mut self) -> PacketWriter {
+        let id = rand::random::<i32>();
+        self.pending_ping = Some(id);
+
+        let mut packet = PacketWriter::create(8);
+        packet.write_packet_type(PacketType::PlayClientboundPing, self.protocol);
+        packet.write_int(id);
+        packet
+    }
+
+    async fn enable_encryption(&mut self, mut decryption: StreamCipher, encryption: StreamCipher) -> Result<(), ConnectionError> {
+        // anything received after the encryption response is already encrypted
+        decryption.decrypt(&mut self.current_packet[self.read_offset..]);
+
+        self.cipher = Some(decryption);
+        self.queue(Outbound::EnableEncryption(Box::new(encryption))).await?;
+        debug!("enabled encryption");
+
+        Ok(())
+    }
+
+    async fn connect_backend(&mut self, backend: SocketAddr, next_state: i32, handshake: &Packet) -> Result<(), ConnectionError> {
+        let mut prelude = Vec::new();
+        if self.config.proxy_protocol {
+            write_proxy_protocol_header(&mut prelude, self.peer, self.local);
         }
+
+        prelude.extend(handshake.encode(self.protocol).map_err(|e| ConnectionError::Other(e.into()))?);
+
+        let permit = match self.server.backend_permits.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => return self.refuse(next_state, "server busy, try again.").await
+        };
+
+        let mut upstream = match TcpStream::connect(backend).await {
+            Ok(upstream) => upstream,
+            Err(e) => {
+                warn!("failed to connect to backend {}: {}", backend, e);
+                self.disconnect("backend unavailable").await;
+                return Ok(());
+            }
+        };
+
+        upstream.write_all(&prelude).await.map_err(|e| ConnectionError::Other(e.into()))?;
+
+        info!("proxying to backend {}", backend);
+        let state = if next_state == 1 { ConnectionState::Status } else { ConnectionState::Login };
+        self.upstream = Some((upstream, state));
+        self.backend_permit = Some(permit);
+        self.state = ConnectionState::Proxying;
+
+        Ok(())
+    }
+
+    async fn proxy(&mut self) {
+        let (mut upstream, state) = self.upstream.take().expect("proxying without a backend");
+        let mut client = match self.close_writer().await {
+            Some(client) => client,
+            None => {
+                self.disconnect("connection closed before proxying").await;
+                return;
+            }
+        };
+
+        // packets of versions without packet ids of their own couldn't be told apart
+        let middleware = self.middleware.clone().filter(|_| self.protocol.is_supported());
+
+        let result = async {
+            let (mut upstream_read, mut upstream_write) = upstream.split();
+
+            if let Some(middleware) = middleware {
+                // whatever the client sent after the handshake goes through the middleware too
+                let pending = self.unparsed().to_vec();
+                self.discard_unparsed();
+
+                let proxied = ProxiedProtocol::create(self.protocol, state);
+                let to_backend = forward_filtered(&mut self.stream, &mut upstream_write, &pending, Direction::Serverbound, middleware.as_ref(), &proxied);
+                let to_client = forward_filtered(&mut upstream_read, &mut client, &[], Direction::Clientbound, middleware.as_ref(), &proxied);
+
+                return tokio::try_join!(to_backend, to_client);
+            }
+
+            // whatever the client sent after the handshake hasn't been forwarded yet
+            upstream_write.write_all(self.unparsed()).await?;
+            self.discard_unparsed();
+
+            let to_backend = async {
+                let sent = tokio::io::copy(&mut self.stream, &mut upstream_write).await?;
+                upstream_write.shutdown().await?;
+                Ok::<_, std::io::Error>(sent)
+            };
+            let to_client = async {
+                let received = tokio::io::copy(&mut upstream_read, &mut client).await?;
+                client.shutdown().await?;
+                Ok::<_, std::io::Error>(received)
+            };
+
+            tokio::try_join!(to_backend, to_client)
+        }.await;
+
+        self.backend_permit = None;
+
+        match result {
+            Ok((sent, received)) => {
+                self.bytes_received += sent;
+                info!("proxied {} bytes to backend and {} bytes back", sent, received);
+                self.disconnect("connection closed").await;
+            }
+            Err(e) => {
+                self.disconnect(&format!("proxy error: {}", e)).await;
+            }
+        }
+    }
+
+    // turns away a client right after its handshake, telling it why if it was logging in
+    async fn refuse(&mut self, next_state: i32, reason: &str) -> Result<(), ConnectionError> {
+        if next_state == 2 {
+            self.state = ConnectionState::Login;
+        }
+
+        self.disconnect(reason).await;
+        Ok(())
+    }
+
+    async fn send_packet(&mut self, packet: PacketWriter) -> Result<(), ConnectionError> {
+        self.queue(Outbound::Packet(packet)).await
+    }
+
+    async fn queue(&mut self, outbound: Outbound) -> Result<(), ConnectionError> {
+        self.outbound.send(outbound).await.map_err(|_| ConnectionError::Other("connection closed".into()))
+    }
+
+    // lets the writer finish the queued packets and takes back the write half of the stream
+    async fn close_writer(&mut self) -> Option<WriteHalf<S>> {
+        let writer = self.writer.take()?;
+        let _ = self.outbound.send(Outbound::Close).await;
+
+        match writer.await {
+            Ok(Ok(stream)) => Some(stream),
+            Ok(Err(e)) => {
+                warn!("failed to write queued packets: {:?}", e);
+                None
+            }
+            Err(e) => {
+                error!("writer failed: {}", e);
+                None
+            }
+        }
+    }
+
+    pub fn packet_sender(&self) -> PacketSender {
+        PacketSender::create(self.outbound.clone())
+    }
+
+    pub async fn disconnect(&mut self, reason: &str) {
+        if self.state == Disconnected {
+            return;
+        }
+
+        info!("disconnecting: {}", reason);
+
+        // clients only show a reason during login and play
+        let packet_type = match self.state {
+            ConnectionState::Login => Some(PacketType::LoginClientboundDisconnect),
+            ConnectionState::Play => Some(PacketType::PlayClientboundDisconnect),
+            _ => None
+        };
+
+        if let Some(packet_type) = packet_type {
+            let mut packet = PacketWriter::create(64);
+            packet.write_packet_type(packet_type, self.protocol);
+            packet.write_string(&serde_json::json!({ "text": reason }).to_string());
+
+            // the client may already be gone, the reason is only a courtesy
+            let _ = self.send_packet(packet).await;
+        }
+
+        self.close(reason).await;
+    }
+
+    // cleans up after the client is gone or was told why it has to go
+    async fn close(&mut self, reason: &str) {
+        if self.state == ConnectionState::Play {
+            self.server.player_left(self.id);
+        }
+
+        self.state = Disconnected;
+        self.discard_unparsed();
+        self.account_buffered();
+        if let Some(mut stream) = self.close_writer().await {
+            match stream.shutdown().await {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::NotConnected => {}
+                Err(e) => warn!("failed to shutdown: {}", e)
+            }
+        }
+
+        if let Some(hooks) = self.hooks.clone() {
+            hooks.on_disconnect(self.id, self.peer, reason.to_string(), self.stats()).await;
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    // the client's address, or the one forwarded by a proxy in front of the server
+    pub fn peer(&self) -> SocketAddr {
+        self.peer
+    }
+
+    pub fn stats(&self) -> ConnectionStats {
+        ConnectionStats {
+            packets_received: self.packets_received,
+            bytes_received: self.bytes_received,
+            duration: self.connected_at.elapsed(),
+        }
+    }
+
+    pub fn with_hooks(mut self, hooks: Arc<dyn ConnectionHooks>) -> Self {
+        self.hooks = Some(hooks);
+        self
+    }
+
+    // lets the middleware see the packets of the connection once it's proxied
+    pub fn with_middleware(mut self, middleware: Arc<dyn PacketMiddleware>) -> Self {
+        self.middleware = Some(middleware);
+        self
+    }
+
+    // logs the client under this name instead of its address
+    pub fn with_peer_name(mut self, peer_name: String) -> Self {
+        self.peer_name = peer_name;
+        self
+    }
+
+    // local is the address the client connected to, which the PROXY protocol header reports
+    pub fn create(stream: S, peer: SocketAddr, local: SocketAddr, server: Arc<Server>) -> Connection<S> {
+        let (read, write) = tokio::io::split(stream);
+        let (outbound, queue) = mpsc::channel(OUTBOUND_QUEUE_SIZE);
+        let writer = tokio::spawn(write_outbound(write, queue, server.config.write_timeout, server.config.simulated_latency));
+        let id = NEXT_CONNECTION_ID.fetch_add(1, Ordering::SeqCst);
+        let buffer_eviction = server.track_buffered(id);
+
+        Connection {
+            id,
+            peer,
+            peer_name: peer.to_string(),
+            local,
+            stream: read,
+            outbound,
+            writer: Some(writer),
+            temp_buffer: Vec::with_capacity(4096),
+            current_packet: Vec::with_capacity(4096),
+            read_offset: 0,
+            state: ConnectionState::Handshake,
+            config: server.config.clone(),
+            shutdown: server.shutdown_signal(),
+            server,
+            protocol_errors: 0,
+            protocol_errors_since: Instant::now(),
+            hooks: None,
+            middleware: None,
+            connected_at: Instant::now(),
+            packets_received: 0,
+            bytes_received: 0,
+            username: None,
+            upstream: None,
+            backend_permit: None,
+            forwarded: None,
+            compression_threshold: None,
+            verify_token: None,
+            cipher: None,
+            buffer_eviction,
+            keep_alive: None,
+            protocol: ProtocolVersion::LATEST_SUPPORTED,
+            position: (0.0, 0.0, 0.0),
+            rotation: (0.0, 0.0),
+            next_teleport_id: 1,
+            pending_teleports: VecDeque::new(),
+            pending_ping: None,
+        }
+    }
+}
+
+impl<S> Drop for Connection<S> {
+    fn drop(&mut self) {
+        self.server.untrack_buffered(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+    use std::time::Duration;
+
+    use tokio::io::DuplexStream;
+
+    use super::*;
+    use crate::config::{ClientWorldFlags, SimulatedLatency};
+    use crate::middleware::Verdict;
+    use crate::packet::payload::Encodable;
+
+    // long enough for anything a test waits for, short enough to fail a hanging test quickly
+    const RECEIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn test_config() -> Config {
+        // frames are easier to take apart uncompressed
+        Config { compression_threshold: -1, ..Config::default() }
+    }
+
+    fn clientbound_id(packet_type: PacketType, protocol: ProtocolVersion) -> i32 {
+        let mut packet = PacketWriter::create(5);
+        packet.write_packet_type(packet_type, protocol);
+        PacketReader::create(packet.as_ref()).read_varint().unwrap()
+    }
+
+    // the id and data of the next uncompressed frame from the stream, None once it's closed
+    async fn receive_frame<R: AsyncRead + Unpin>(stream: &mut R, buffer: &mut Vec<u8>) -> Option<(i32, Vec<u8>)> {
+        loop {
+            if let Ok(frame_size) = Packet::frame_size(buffer) {
+                let frame: Vec<u8> = buffer.drain(..frame_size).collect();
+                let mut reader = PacketReader::create(&frame);
+                reader.read_varint().unwrap();
+                let id = reader.read_varint().unwrap();
+
+                return Some((id, reader.read_remaining().to_vec()));
+            }
+
+            let read = tokio::time::timeout(RECEIVE_TIMEOUT, stream.read_buf(buffer)).await
+                .expect("timed out waiting for the other side");
+
+            if read.unwrap() == 0 {
+                return None;
+            }
+        }
+    }
+
+    // the client end of a connection served over an in-memory pipe
+    struct TestClient {
+        stream: DuplexStream,
+        buffer: Vec<u8>,
+        protocol: ProtocolVersion,
+        connection: JoinHandle<()>,
+    }
+
+    impl TestClient {
+        fn connect(config: Config) -> (TestClient, Arc<Server>) {
+            let server = Arc::new(Server::create(config));
+            server.set_ready();
+
+            (TestClient::connect_to(server.clone()), server)
+        }
+
+        fn connect_to(server: Arc<Server>) -> TestClient {
+            TestClient::connect_with(server, |connection| connection)
+        }
+
+        fn connect_with<F>(server: Arc<Server>, setup: F) -> TestClient
+            where F: FnOnce(Connection<DuplexStream>) -> Connection<DuplexStream> + Send + 'static {
+            let (client, stream) = tokio::io::duplex(64 * 1024);
+            let peer = SocketAddr::from(([127, 0, 0, 1], 50000));
+
+            let connection = tokio::spawn(async move {
+                setup(Connection::create(stream, peer, peer, server)).process().await;
+            });
+
+            TestClient { stream: client, buffer: Vec::new(), protocol: ProtocolVersion::V1_19_4, connection }
+        }
+
+        async fn send_raw(&mut self, bytes: &[u8]) {
+            self.stream.write_all(bytes).await.unwrap();
+        }
+
+        async fn send<F: FnOnce(&mut PacketWriter)>(&mut self, packet_type: PacketType, write: F) {
+            let frame = serverbound(packet_type, write).encode(self.protocol).unwrap();
+            self.send_raw(&frame).await;
+        }
+
+        async fn handshake(&mut self, protocol: i32, next_state: i32) {
+            self.send(PacketType::HandshakeServerboundStart, |packet| Handshake {
+                protocol_version: protocol,
+                host: "localhost".to_string(),
+                port: 25565,
+                next_state,
+            }.encode(packet)).await;
+        }
+
+        async fn login_start(&mut self, name: &str) {
+            self.handshake(self.protocol.as_i32(), 2).await;
+            self.send(PacketType::LoginServerboundStart, |packet| LoginStart {
+                name: name.to_string(),
+                uuid: None,
+            }.encode(packet)).await;
+        }
+
+        // logs in and returns the data of the login success
+        async fn login(&mut self, name: &str) -> Vec<u8> {
+            self.login_start(name).await;
+            self.expect(PacketType::LoginClientboundSuccess).await
+        }
+
+        async fn send_chat(&mut self, message: &str) {
+            self.send(PacketType::PlayServerboundChatMessage, |packet| {
+                packet.write_string(message);
+                packet.write_long(0); // timestamp
+                packet.write_long(0); // salt
+                packet.write_boolean(false); // no signature
+                packet.write_var_int(0); // message count
+                packet.write_all(&[0; 3]).unwrap(); // acknowledged
+            }).await;
+        }
+
+        // the chat of the player comes back to it, which shows that everything sent before was handled
+        async fn expect_alive(&mut self) {
+            self.send_chat("ping").await;
+            let chat = self.expect(PacketType::PlayClientboundSystemChatMessage).await;
+
+            assert!(PacketReader::create(&chat).read_string(32767).unwrap().contains("ping"));
+        }
+
+        // the id and data of the next packet, None once the connection is closed
+        async fn receive(&mut self) -> Option<(i32, Vec<u8>)> {
+            receive_frame(&mut self.stream, &mut self.buffer).await
+        }
+
+        // skips packets until one of the type arrives and returns its data
+        async fn expect(&mut self, packet_type: PacketType) -> Vec<u8> {
+            let id = clientbound_id(packet_type, self.protocol);
+
+            loop {
+                match self.receive().await {
+                    Some((received, data)) if received == id => return data,
+                    Some(_) => {}
+                    None => panic!("connection closed while waiting for {:?}", packet_type)
+                }
+            }
+        }
+
+        // the reason of the disconnect packet the client is sent before the connection closes
+        async fn expect_disconnect(&mut self, packet_type: PacketType) -> String {
+            let data = self.expect(packet_type).await;
+            let reason = PacketReader::create(&data).read_string(32767).unwrap();
+            self.assert_closed().await;
+
+            reason
+        }
+
+        // waits for the server to close the connection, skipping what it sends until then
+        async fn assert_closed(&mut self) {
+            while self.receive().await.is_some() {}
+        }
+
+        // closes the client end and waits for the connection to finish, which fails when it panicked
+        async fn finish(mut self) {
+            self.stream.shutdown().await.unwrap();
+            self.assert_closed().await;
+            self.connection.await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn container_packets_are_skipped_in_step_with_the_stream() {
+        let (mut client, _) = TestClient::connect(Config { strict_packets: true, trailing_bytes: TrailingBytesPolicy::Disconnect, ..test_config() });
+        client.login("Steve").await;
+
+        client.send(PacketType::PlayServerboundCloseContainer, |packet| packet.write_ubyte(1)).await;
+        client.send(PacketType::PlayServerboundClickContainer, |packet| {
+            packet.write_ubyte(1); // window id
+            packet.write_var_int(7); // state id
+            packet.write_short(36); // slot
+            packet.write_byte(0); // button
+            packet.write_var_int(0); // mode
+            packet.write_var_int(1); // changed slots
+            packet.write_short(36);
+            packet.write_boolean(false); // empty slot
+            packet.write_boolean(false); // carried item
+        }).await;
+
+        client.expect_alive().await;
+        client.finish().await;
+    }
+
+    // protocol numbers of snapshots have bit 30 set
+    const SNAPSHOT_PROTOCOL: i32 = 0x40000000 | 144;
+
+    #[tokio::test]
+    async fn snapshot_logins_are_refused_by_default() {
+        let (mut client, _) = TestClient::connect(test_config());
+        client.handshake(SNAPSHOT_PROTOCOL, 2).await;
+
+        let reason = client.expect_disconnect(PacketType::LoginClientboundDisconnect).await;
+        assert!(reason.contains("Unsupported protocol version"), "{}", reason);
+    }
+
+    #[tokio::test]
+    async fn snapshot_logins_are_accepted_with_experimental_protocols() {
+        let (mut client, _) = TestClient::connect(Config { experimental_protocols: true, ..test_config() });
+        client.protocol = ProtocolVersion::from_i32(SNAPSHOT_PROTOCOL);
+
+        client.login("Steve").await;
+        client.finish().await;
+    }
+
+    #[tokio::test]
+    async fn snapshots_are_shown_the_supported_version_in_the_server_list() {
+        let (mut client, _) = TestClient::connect(test_config());
+        client.handshake(SNAPSHOT_PROTOCOL, 1).await;
+        client.send(PacketType::StatusServerboundRequest, |_| {}).await;
+
+        let response = client.expect(PacketType::StatusClientboundResponse).await;
+        let status = StatusResponse::parse(&PacketReader::create(&response).read_string(32767).unwrap()).unwrap();
+        assert_eq!(status.version.protocol, ProtocolVersion::LATEST_SUPPORTED.as_i32());
+
+        client.finish().await;
+    }
+
+    #[tokio::test]
+    async fn difficulty_is_sent_as_configured() {
+        let (mut client, _) = TestClient::connect(Config {
+            difficulty: crate::config::Difficulty::Hard,
+            difficulty_locked: true,
+            ..test_config()
+        });
+        client.login("Steve").await;
+
+        assert_eq!(client.expect(PacketType::PlayClientboundDifficulty).await, [3, 1]);
+        client.finish().await;
+    }
+
+    // a frame whose packet id is a varint that doesn't fit an i32
+    const MALFORMED_FRAME: [u8; 6] = [5, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+
+    #[tokio::test]
+    async fn malformed_packets_below_the_error_limit_are_skipped() {
+        let (mut client, _) = TestClient::connect(Config { max_protocol_errors: 3, ..test_config() });
+        for _ in 0..3 {
+            client.send_raw(&MALFORMED_FRAME).await;
+        }
+
+        client.handshake(762, 1).await;
+        client.send(PacketType::StatusServerboundRequest, |_| {}).await;
+        client.expect(PacketType::StatusClientboundResponse).await;
+        client.finish().await;
+    }
+
+    #[tokio::test]
+    async fn clients_exceeding_the_error_limit_are_dropped() {
+        let (mut client, _) = TestClient::connect(Config { max_protocol_errors: 3, ..test_config() });
+        for _ in 0..4 {
+            client.send_raw(&MALFORMED_FRAME).await;
+        }
+
+        client.assert_closed().await;
+    }
+
+    #[derive(Default)]
+    struct RecordingHooks {
+        events: std::sync::Mutex<Vec<String>>,
+    }
+
+    impl ConnectionHooks for RecordingHooks {
+        fn on_connect(&self, _id: u64, peer: SocketAddr) -> HookFuture<'_> {
+            self.events.lock().unwrap().push(format!("connect {}", peer));
+            Box::pin(async {})
+        }
+
+        fn on_disconnect(&self, _id: u64, _peer: SocketAddr, reason: String, stats: ConnectionStats) -> HookFuture<'_> {
+            self.events.lock().unwrap().push(format!("disconnect {} after {} packets", reason, stats.packets_received));
+            Box::pin(async {})
+        }
+    }
+
+    #[tokio::test]
+    async fn hooks_fire_once_when_the_client_quits() {
+        let hooks = Arc::new(RecordingHooks::default());
+        let server = Arc::new(Server::create(test_config()));
+        let recording = hooks.clone();
+        let mut client = TestClient::connect_with(server, move |connection| connection.with_hooks(recording));
+
+        client.handshake(762, 1).await;
+        client.send(PacketType::StatusServerboundRequest, |_| {}).await;
+        client.expect(PacketType::StatusClientboundResponse).await;
+        client.finish().await;
+
+        assert_eq!(*hooks.events.lock().unwrap(), ["connect 127.0.0.1:50000", "disconnect end of stream after 2 packets"]);
+    }
+
+    #[tokio::test]
+    async fn hooks_fire_once_when_the_client_is_kicked() {
+        let hooks = Arc::new(RecordingHooks::default());
+        let server = Arc::new(Server::create(test_config()));
+        let recording = hooks.clone();
+        let mut client = TestClient::connect_with(server, move |connection| connection.with_hooks(recording));
+
+        // the server isn't ready, so the login is refused
+        client.login_start("Steve").await;
+        client.expect_disconnect(PacketType::LoginClientboundDisconnect).await;
+        client.finish().await;
+
+        let events = hooks.events.lock().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0], "connect 127.0.0.1:50000");
+        assert!(events[1].starts_with("disconnect The server is still starting up"), "{}", events[1]);
+    }
+
+    // records the packets it sees and drops chat messages
+    #[derive(Default)]
+    struct ChatFilter {
+        seen: std::sync::Mutex<Vec<(Direction, PacketType)>>,
+    }
+
+    impl PacketMiddleware for ChatFilter {
+        fn on_packet(&self, direction: Direction, packet: &Packet) -> Verdict {
+            self.seen.lock().unwrap().push((direction, packet.packet_type));
+
+            match packet.packet_type {
+                PacketType::PlayServerboundChatMessage => Verdict::Drop,
+                _ => Verdict::Pass
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn middleware_keeps_dropped_packets_from_the_backend() {
+        let backend = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = Arc::new(Server::create(Config { backend: Some(backend.local_addr().unwrap()), ..test_config() }));
+        server.set_ready();
+
+        let filter = Arc::new(ChatFilter::default());
+        let middleware = filter.clone();
+        let mut client = TestClient::connect_with(server, move |connection| connection.with_middleware(middleware));
+        client.login_start("Steve").await;
+
+        let (mut upstream, _) = backend.accept().await.unwrap();
+        let mut buffer = Vec::new();
+        let handshake = receive_frame(&mut upstream, &mut buffer).await.unwrap();
+        let login_start = receive_frame(&mut upstream, &mut buffer).await.unwrap();
+        assert_eq!((handshake.0, login_start.0), (0x00, 0x00));
+
+        let mut success = PacketWriter::create(32);
+        success.write_uuid(offline_uuid("Steve"));
+        success.write_string("Steve");
+        success.write_properties(&[]);
+        let success = Packet { data: success.as_ref().to_vec(), raw_size: 0, packet_type: PacketType::LoginClientboundSuccess };
+        upstream.write_all(&success.encode(client.protocol).unwrap()).await.unwrap();
+        client.expect(PacketType::LoginClientboundSuccess).await;
+
+        client.send_chat("hello").await;
+        client.send(PacketType::PlayServerboundKeepAlive, |packet| packet.write_long(7)).await;
+
+        // the keep-alive is the next thing the backend receives
+        let (_, data) = receive_frame(&mut upstream, &mut buffer).await.unwrap();
+        assert_eq!(data, 7i64.to_be_bytes());
+
+        drop(upstream);
+        client.finish().await;
+
+        assert_eq!(*filter.seen.lock().unwrap(), [
+            (Direction::Serverbound, PacketType::LoginServerboundStart),
+            (Direction::Clientbound, PacketType::LoginClientboundSuccess),
+            (Direction::Serverbound, PacketType::PlayServerboundChatMessage),
+            (Direction::Serverbound, PacketType::PlayServerboundKeepAlive),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn block_interactions_are_read_completely() {
+        let (mut client, _) = TestClient::connect(Config { strict_packets: true, trailing_bytes: TrailingBytesPolicy::Disconnect, ..test_config() });
+        client.login("Steve").await;
+
+        client.send(PacketType::PlayServerboundPlayerAction, |packet| {
+            packet.write_var_int(0); // started digging
+            packet.write_position(-12, 64, 300);
+            packet.write_byte(1); // top face
+            packet.write_var_int(5);
+        }).await;
+        client.send(PacketType::PlayServerboundUseItem, |packet| {
+            packet.write_var_int(1); // off hand
+            packet.write_var_int(6);
+        }).await;
+
+        client.expect_alive().await;
+        client.finish().await;
+    }
+
+    #[tokio::test]
+    async fn world_flags_are_sent_in_the_join_packet() {
+        let world_flags = ClientWorldFlags { reduced_debug_info: true, enable_respawn_screen: true, is_debug: true, is_flat: true };
+        let (mut client, _) = TestClient::connect(Config { world_flags, ..test_config() });
+        client.login("Steve").await;
+
+        // the four flags, then no death location
+        let login = client.expect(PacketType::PlayClientboundLogin).await;
+        assert!(login.ends_with(&[1, 1, 1, 1, 0]));
+
+        client.finish().await;
+    }
+
+    #[tokio::test]
+    async fn status_pings_are_answered_without_the_backend_when_configured() {
+        let backend = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = Config { backend: Some(backend.local_addr().unwrap()), answer_status_locally: true, ..test_config() };
+        let (mut client, _) = TestClient::connect(config);
+
+        client.handshake(client.protocol.as_i32(), 1).await;
+        client.send(PacketType::StatusServerboundRequest, |_| {}).await;
+        let status = client.expect(PacketType::StatusClientboundResponse).await;
+        assert!(PacketReader::create(&status).read_string(32767).unwrap().contains("\"description\""));
+
+        assert!(tokio::time::timeout(Duration::from_millis(100), backend.accept()).await.is_err());
+        client.finish().await;
+    }
+
+    // collects what is logged on the thread that started it, which runs the connections of a test
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl CapturedLogs {
+        fn start(&self) -> tracing::subscriber::DefaultGuard {
+            let logs = self.clone();
+            let subscriber = tracing_subscriber::fmt()
+                .with_writer(move || logs.clone())
+                .with_ansi(false)
+                .with_max_level(tracing::Level::DEBUG)
+                .finish();
+
+            tracing::subscriber::set_default(subscriber)
+        }
+
+        fn contents(&self) -> String {
+            String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+        }
+    }
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // closes a container and sends a byte more than the packet has
+    async fn send_with_trailing_byte(client: &mut TestClient) {
+        client.send(PacketType::PlayServerboundCloseContainer, |packet| {
+            packet.write_ubyte(0);
+            packet.write_ubyte(0xAB);
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn trailing_bytes_disconnect_when_configured() {
+        let (mut client, _) = TestClient::connect(Config { trailing_bytes: TrailingBytesPolicy::Disconnect, ..test_config() });
+        client.login("Steve").await;
+
+        send_with_trailing_byte(&mut client).await;
+        let reason = client.expect_disconnect(PacketType::PlayClientboundDisconnect).await;
+        assert!(reason.contains("TrailingBytes(1)"), "{}", reason);
+    }
+
+    #[tokio::test]
+    async fn trailing_bytes_only_warn_when_configured() {
+        let logs = CapturedLogs::default();
+        let _guard = logs.start();
+
+        let (mut client, _) = TestClient::connect(Config { trailing_bytes: TrailingBytesPolicy::Warn, ..test_config() });
+        client.login("Steve").await;
+
+        send_with_trailing_byte(&mut client).await;
+        client.expect_alive().await;
+        client.finish().await;
+
+        assert!(logs.contents().contains("TrailingBytes(1) left in packet of type PlayServerboundCloseContainer"), "{}", logs.contents());
+    }
+
+    #[tokio::test]
+    async fn log_lines_carry_the_username_once_known() {
+        let logs = CapturedLogs::default();
+        let _guard = logs.start();
+
+        let (mut client, _) = TestClient::connect(test_config());
+        client.login("Steve").await;
+        client.finish().await;
+
+        let logs = logs.contents();
+        let connected = logs.lines().find(|line| line.ends_with("connected")).unwrap();
+        let logging_in = logs.lines().find(|line| line.contains("Player logging in")).unwrap();
+
+        assert!(connected.contains("connection{id=") && connected.contains("peer=127.0.0.1:50000"), "{}", connected);
+        assert!(!connected.contains("username="), "{}", connected);
+        assert!(logging_in.contains("username=\"Steve\""), "{}", logging_in);
+    }
+
+    #[tokio::test]
+    async fn pongs_of_the_sent_ping_are_accepted() {
+        let (mut client, _) = TestClient::connect(Config { strict_packets: true, trailing_bytes: TrailingBytesPolicy::Disconnect, ..test_config() });
+        client.login("Steve").await;
+
+        let ping = client.expect(PacketType::PlayClientboundPing).await;
+        let id = PacketReader::create(&ping).read_int().unwrap();
+        client.send(PacketType::PlayServerboundPong, |packet| packet.write_int(id)).await;
+
+        client.expect_alive().await;
+        client.finish().await;
+    }
+
+    #[tokio::test]
+    async fn pongs_of_unknown_pings_are_ignored() {
+        let logs = CapturedLogs::default();
+        let _guard = logs.start();
+
+        let (mut client, _) = TestClient::connect(test_config());
+        client.login("Steve").await;
+
+        let ping = client.expect(PacketType::PlayClientboundPing).await;
+        let id = PacketReader::create(&ping).read_int().unwrap();
+        client.send(PacketType::PlayServerboundPong, |packet| packet.write_int(id.wrapping_add(1))).await;
+
+        client.expect_alive().await;
+        client.finish().await;
+
+        assert!(logs.contents().contains(&format!("ignoring pong of unknown ping {}", id.wrapping_add(1))));
+    }
+
+    #[tokio::test]
+    async fn logins_beyond_the_backend_connection_limit_are_refused() {
+        let backend = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = Config { backend: Some(backend.local_addr().unwrap()), max_backend_connections: 1, ..test_config() };
+        let (mut client, server) = TestClient::connect(config);
+        let _held = server.backend_permits.clone().try_acquire_owned().unwrap();
+
+        client.login_start("Steve").await;
+        let reason = client.expect_disconnect(PacketType::LoginClientboundDisconnect).await;
+        assert!(reason.contains("server busy, try again."), "{}", reason);
+
+        assert!(tokio::time::timeout(Duration::from_millis(100), backend.accept()).await.is_err());
+    }
+
+    #[cfg(debug_assertions)]
+    #[tokio::test]
+    async fn misaligned_buffers_are_reported() {
+        let logs = CapturedLogs::default();
+        let _guard = logs.start();
+
+        let (_client, stream) = tokio::io::duplex(64);
+        let peer = SocketAddr::from(([127, 0, 0, 1], 50000));
+        let mut connection = Connection::create(stream, peer, peer, Arc::new(Server::create(test_config())));
+
+        // two status pings with a payload of zeros
+        let ping = [9, 0x01, 0, 0, 0, 0, 0, 0, 0, 0];
+        connection.current_packet = [ping, ping].concat();
+
+        connection.consume(ping.len());
+        connection.check_frame_alignment();
+        assert!(!logs.contents().contains("misaligned"), "{}", logs.contents());
+
+        // one byte short of the end of the frame, into its payload
+        connection.read_offset = ping.len() - 1;
+        connection.check_frame_alignment();
+        assert!(logs.contents().contains("buffer misaligned after packet, next frame length is Ok(0)"), "{}", logs.contents());
+    }
+
+    #[tokio::test]
+    async fn logins_without_a_route_are_refused() {
+        let routes = HashMap::from([("play.example.com".to_string(), SocketAddr::from(([127, 0, 0, 1], 1)))]);
+        let (mut client, _) = TestClient::connect(Config { routes, ..test_config() });
+
+        // the test client connects to localhost
+        client.login_start("Steve").await;
+        let reason = client.expect_disconnect(PacketType::LoginClientboundDisconnect).await;
+        assert!(reason.contains("no route for localhost"), "{}", reason);
+    }
+
+    #[tokio::test]
+    async fn forwarding_data_is_refused_when_forwarding_is_disabled() {
+        let (mut client, _) = TestClient::connect(test_config());
+
+        client.send(PacketType::HandshakeServerboundStart, |packet| Handshake {
+            protocol_version: ProtocolVersion::V1_19_4.as_i32(),
+            host: "localhost\u{0}203.0.113.7\u{0}069a79f444e94726a5befca90e38aaf5".to_string(),
+            port: 25565,
+            next_state: 2,
+        }.encode(packet)).await;
+
+        client.assert_closed().await;
+    }
+
+    #[tokio::test]
+    async fn compression_is_announced_before_the_login_succeeds() {
+        let (mut client, _) = TestClient::connect(Config { compression_threshold: 64, ..test_config() });
+        client.login_start("Steve").await;
+
+        let (id, data) = client.receive().await.unwrap();
+        assert_eq!(id, clientbound_id(PacketType::LoginClientboundSetCompression, client.protocol));
+        assert_eq!(data, [64]);
+    }
+
+    #[tokio::test]
+    async fn compression_is_not_announced_when_disabled() {
+        let (mut client, _) = TestClient::connect(Config { compression_threshold: -1, ..test_config() });
+        client.login_start("Steve").await;
+
+        let (id, _) = client.receive().await.unwrap();
+        assert_eq!(id, clientbound_id(PacketType::LoginClientboundSuccess, client.protocol));
+    }
+
+    // what a 1.4 to 1.6 client sends first when pinging
+    const LEGACY_PING_REQUEST: [u8; 3] = [0xFE, 0x01, 0xFA];
+
+    #[tokio::test]
+    async fn legacy_pings_are_answered_when_enabled() {
+        let (mut client, _) = TestClient::connect(Config { legacy_ping: true, max_players: 20, ..test_config() });
+        client.send_raw(&LEGACY_PING_REQUEST).await;
+
+        let mut response = Vec::new();
+        tokio::time::timeout(RECEIVE_TIMEOUT, client.stream.read_to_end(&mut response)).await.unwrap().unwrap();
+
+        let length = u16::from_be_bytes([response[1], response[2]]) as usize;
+        let text: Vec<u16> = response[3..].chunks(2).map(|unit| u16::from_be_bytes([unit[0], unit[1]])).collect();
+        let text = String::from_utf16(&text).unwrap();
+
+        assert_eq!(response[0], 0xFF);
+        assert_eq!(text.encode_utf16().count(), length);
+        assert!(text.starts_with("\u{a7}1\u{0}127\u{0}") && text.ends_with("\u{0}0\u{0}20"), "{}", text);
+    }
+
+    #[tokio::test]
+    async fn legacy_pings_are_not_answered_when_disabled() {
+        let (mut client, _) = TestClient::connect(Config { legacy_ping: false, ..test_config() });
+        client.send_raw(&LEGACY_PING_REQUEST).await;
+
+        let mut response = [0; 1];
+        assert!(tokio::time::timeout(Duration::from_millis(100), client.stream.read(&mut response)).await.is_err());
+
+        client.finish().await;
+    }
+
+    #[tokio::test]
+    async fn logins_are_refused_until_the_server_is_ready() {
+        let server = Arc::new(Server::create(test_config()));
+
+        let mut client = TestClient::connect_with(server.clone(), |connection| connection);
+        client.login_start("Steve").await;
+        let reason = client.expect_disconnect(PacketType::LoginClientboundDisconnect).await;
+        assert!(reason.contains("still starting up"), "{}", reason);
+
+        server.set_ready();
+        let mut client = TestClient::connect_to(server);
+        client.login("Steve").await;
+        client.finish().await;
+    }
+
+    #[tokio::test]
+    async fn the_server_list_shows_the_server_starting_up() {
+        let server = Arc::new(Server::create(test_config()));
+        let mut client = TestClient::connect_with(server, |connection| connection);
+
+        client.handshake(client.protocol.as_i32(), 1).await;
+        client.send(PacketType::StatusServerboundRequest, |_| {}).await;
+        let status = client.expect(PacketType::StatusClientboundResponse).await;
+
+        assert!(PacketReader::create(&status).read_string(32767).unwrap().contains("Starting up..."));
+        client.finish().await;
+    }
+
+    fn write_command(packet: &mut PacketWriter, command: &str, signed_arguments: &[&str]) {
+        packet.write_string(command);
+        packet.write_long(0); // timestamp
+        packet.write_long(0); // salt
+        packet.write_var_int(signed_arguments.len() as i32);
+        for argument in signed_arguments {
+            packet.write_string(argument);
+            packet.write_all(&[0; MESSAGE_SIGNATURE_LENGTH]).unwrap();
+        }
+        packet.write_var_int(0); // message count
+        packet.write_all(&[0; 3]).unwrap(); // acknowledged
+    }
+
+    #[tokio::test]
+    async fn commands_are_read_completely() {
+        let logs = CapturedLogs::default();
+        let _guard = logs.start();
+
+        let (mut client, _) = TestClient::connect(Config { strict_packets: true, trailing_bytes: TrailingBytesPolicy::Disconnect, ..test_config() });
+        client.login("Steve").await;
+
+        client.send(PacketType::PlayServerboundChatCommand, |packet| write_command(packet, "help", &[])).await;
+        client.send(PacketType::PlayServerboundChatCommand, |packet| write_command(packet, "msg Alex hi", &["message"])).await;
+        client.expect_alive().await;
+        client.finish().await;
+
+        assert!(logs.contents().contains("ran command /help"));
+        assert!(logs.contents().contains("ran command /msg Alex hi"));
+    }
+
+    #[tokio::test]
+    async fn commands_with_too_many_signatures_are_refused() {
+        let (mut client, _) = TestClient::connect(test_config());
+        client.login("Steve").await;
+
+        let arguments = vec!["argument"; MAX_COMMAND_ARGUMENT_SIGNATURES + 1];
+        client.send(PacketType::PlayServerboundChatCommand, |packet| write_command(packet, "say", &arguments)).await;
+
+        let reason = client.expect_disconnect(PacketType::PlayClientboundDisconnect).await;
+        assert!(reason.contains("too many command argument signatures"), "{}", reason);
+    }
+
+    // a received packet as the PacketWriter it was sent from holds it
+    fn unframed(id: i32, data: &[u8]) -> Vec<u8> {
+        let mut packet = PacketWriter::create(data.len() + 5);
+        packet.write_var_int(id);
+        packet.write_all(data).unwrap();
+        packet.as_ref().to_vec()
+    }
+
+    #[tokio::test]
+    async fn status_responses_are_the_bytes_of_the_public_builders() {
+        let (mut client, server) = TestClient::connect(test_config());
+        client.handshake(client.protocol.as_i32(), 1).await;
+
+        client.send(PacketType::StatusServerboundRequest, |_| {}).await;
+        let (id, data) = client.receive().await.unwrap();
+
+        let status = StatusResponse {
+            version: Version { name: client.protocol.name(), protocol: client.protocol.as_i32() },
+            players: Players { max: server.config.max_players as i32, online: 0, sample: Vec::new() },
+            description: serde_json::from_str(&server.config.description).unwrap(),
+            favicon: server.favicon.clone(),
+        };
+        assert_eq!(unframed(id, &data), build_status_response(&status, client.protocol).unwrap().as_ref());
+
+        client.send(PacketType::StatusServerboundPing, |packet| packet.write_long(42)).await;
+        let (id, data) = client.receive().await.unwrap();
+        assert_eq!(unframed(id, &data), build_pong(42, client.protocol).as_ref());
+
+        client.finish().await;
+    }
+
+    // waits for the connections of the server to have buffered bytes in total
+    async fn wait_for_buffered(server: &Server, bytes: usize) {
+        tokio::time::timeout(RECEIVE_TIMEOUT, async {
+            while server.buffered_bytes() != bytes {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        }).await.expect("timed out waiting for the bytes to be buffered");
+    }
+
+    #[tokio::test]
+    async fn the_connections_buffering_the_most_are_dropped_past_the_limit() {
+        let (mut hoarder, server) = TestClient::connect(Config { max_buffered_bytes: 100, ..test_config() });
+        let mut client = TestClient::connect_to(server.clone());
+
+        // the start of a frame of 200 bytes that never ends
+        let mut hoarded = vec![0xC8, 0x01];
+        hoarded.resize(90, 0);
+        hoarder.send_raw(&hoarded).await;
+        wait_for_buffered(&server, 90).await;
+
+        // the login of the other client arrives in two pieces, the first of which crosses the limit
+        let mut login = PacketWriter::create(64);
+        Handshake { protocol_version: client.protocol.as_i32(), host: "localhost".to_string(), port: 25565, next_state: 2 }.encode(&mut login);
+        let handshake = Packet { data: login.as_ref().to_vec(), raw_size: 0, packet_type: PacketType::HandshakeServerboundStart };
+        let mut login = PacketWriter::create(16);
+        LoginStart { name: "Steve".to_string(), uuid: None }.encode(&mut login);
+        let login_start = Packet { data: login.as_ref().to_vec(), raw_size: 0, packet_type: PacketType::LoginServerboundStart };
+        let login = [handshake.encode(client.protocol).unwrap(), login_start.encode(client.protocol).unwrap()].concat();
+
+        client.send_raw(&login[..20]).await;
+        hoarder.assert_closed().await;
+
+        client.send_raw(&login[20..]).await;
+        client.expect(PacketType::LoginClientboundSuccess).await;
+        client.finish().await;
+    }
+
+    #[tokio::test]
+    async fn truncated_packets_disconnect_without_a_panic() {
+        let (mut client, _) = TestClient::connect(test_config());
+        client.handshake(client.protocol.as_i32(), 2).await;
+
+        // a name of 5 bytes with only one of them sent
+        client.send(PacketType::LoginServerboundStart, |packet| packet.write_all(&[5, b'S']).unwrap()).await;
+        let reason = client.expect_disconnect(PacketType::LoginClientboundDisconnect).await;
+        assert!(reason.contains("protocol error"), "{}", reason);
+
+        client.connection.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn truncated_play_packets_disconnect_without_a_panic() {
+        let truncated: [(PacketType, &[u8]); 4] = [
+            (PacketType::PlayServerboundChatMessage, &[2, b'h', b'i', 0, 0]),
+            (PacketType::PlayServerboundKeepAlive, &[0, 0, 0]),
+            (PacketType::PlayServerboundSetPlayerPosition, &[0; 20]),
+            (PacketType::PlayServerboundPlayerAction, &[0, 0, 0]),
+        ];
+
+        for (packet_type, data) in truncated {
+            let (mut client, _) = TestClient::connect(test_config());
+            client.login("Steve").await;
+
+            client.send(packet_type, |packet| packet.write_all(data).unwrap()).await;
+            let reason = client.expect_disconnect(PacketType::PlayClientboundDisconnect).await;
+            assert!(reason.contains("protocol error"), "{:?}: {}", packet_type, reason);
+
+            client.connection.await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn keep_alives_with_the_wrong_id_disconnect() {
+        let (mut client, _) = TestClient::connect(test_config());
+        client.login("Steve").await;
+
+        let keep_alive = client.expect(PacketType::PlayClientboundKeepAlive).await;
+        let id = PacketReader::create(&keep_alive).read_long().unwrap();
+        client.send(PacketType::PlayServerboundKeepAlive, |packet| packet.write_long(id)).await;
+        client.expect_alive().await;
+
+        client.send(PacketType::PlayServerboundKeepAlive, |packet| packet.write_long(id)).await;
+        let reason = client.expect_disconnect(PacketType::PlayClientboundDisconnect).await;
+        assert!(reason.contains("invalid keep alive"), "{}", reason);
+    }
+
+    #[tokio::test]
+    async fn disconnect_reasons_are_chat_components() {
+        // the server isn't ready, so the login is refused
+        let mut client = TestClient::connect_to(Arc::new(Server::create(test_config())));
+        client.login_start("Steve").await;
+
+        let reason = client.expect_disconnect(PacketType::LoginClientboundDisconnect).await;
+        let component: serde_json::Value = serde_json::from_str(&reason).unwrap();
+        assert_eq!(component, serde_json::json!({ "text": "The server is still starting up, try again shortly." }));
+
+        let (mut client, _) = TestClient::connect(test_config());
+        client.login("Steve").await;
+        client.send(PacketType::PlayServerboundKeepAlive, |packet| packet.write_long(-1)).await;
+
+        let reason = client.expect_disconnect(PacketType::PlayClientboundDisconnect).await;
+        let component: serde_json::Value = serde_json::from_str(&reason).unwrap();
+        assert!(component["text"].as_str().unwrap().contains("invalid keep alive"), "{}", reason);
+    }
+
+    #[tokio::test]
+    async fn disconnects_before_login_only_close_the_connection() {
+        let (mut client, _) = TestClient::connect(test_config());
+        client.handshake(client.protocol.as_i32(), 3).await;
+
+        assert!(client.receive().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn strict_mode_accepts_ignored_packets_but_not_unknown_ones() {
+        let (mut client, _) = TestClient::connect(Config { strict_packets: true, ..test_config() });
+        client.login("Steve").await;
+
+        client.send(PacketType::PlayServerboundMessageAcknowledgment, |packet| packet.write_var_int(1)).await;
+        client.expect_alive().await;
+
+        // an id no packet type is registered for
+        let packet = unframed(0x7F, &[1, 2, 3]);
+        client.send_raw(&[packet.len() as u8]).await;
+        client.send_raw(&packet).await;
+        client.assert_closed().await;
+    }
+
+    #[tokio::test]
+    async fn unignored_packets_disconnect_in_strict_mode() {
+        let (mut client, _) = TestClient::connect(Config { strict_packets: true, ignored_packets: HashSet::new(), ..test_config() });
+        client.login("Steve").await;
+
+        client.send(PacketType::PlayServerboundMessageAcknowledgment, |packet| packet.write_var_int(1)).await;
+        let reason = client.expect_disconnect(PacketType::PlayClientboundDisconnect).await;
+        assert!(reason.contains("Invalid packet"), "{}", reason);
+    }
+
+    #[tokio::test]
+    async fn status_responses_count_the_players_online() {
+        let (mut player, server) = TestClient::connect(Config { max_players: 42, ..test_config() });
+        player.login("Steve").await;
+
+        let mut client = TestClient::connect_to(server);
+        client.handshake(client.protocol.as_i32(), 1).await;
+        client.send(PacketType::StatusServerboundRequest, |_| {}).await;
+
+        let data = client.expect(PacketType::StatusClientboundResponse).await;
+        let status = StatusResponse::parse(&PacketReader::create(&data).read_string(32767).unwrap()).unwrap();
+
+        assert_eq!(status.players.online, 1);
+        assert_eq!(status.players.max, 42);
+        assert_eq!(status.version.protocol, client.protocol.as_i32());
+    }
+
+    #[tokio::test]
+    async fn frame_lengths_split_across_reads_are_waited_for() {
+        let (mut client, _) = TestClient::connect(test_config());
+
+        // a long host makes the frame length take two bytes
+        let mut handshake = PacketWriter::create(256);
+        Handshake { protocol_version: client.protocol.as_i32(), host: "a".repeat(200), port: 25565, next_state: 2 }.encode(&mut handshake);
+        let frame = Packet { data: handshake.as_ref().to_vec(), raw_size: 0, packet_type: PacketType::HandshakeServerboundStart }
+            .encode(client.protocol)
+            .unwrap();
+        assert!(frame[0] & 0x80 != 0);
+
+        client.send_raw(&frame[..1]).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        client.send_raw(&frame[1..]).await;
+
+        client.send(PacketType::LoginServerboundStart, |packet| LoginStart { name: "Steve".to_string(), uuid: None }.encode(packet)).await;
+        client.expect(PacketType::LoginClientboundSuccess).await;
+        client.finish().await;
+    }
+
+    fn playing_connection() -> Connection<DuplexStream> {
+        let (_, stream) = tokio::io::duplex(64 * 1024);
+        let peer = SocketAddr::from(([127, 0, 0, 1], 50000));
+
+        let mut connection = Connection::create(stream, peer, peer, Arc::new(Server::create(test_config())));
+        connection.state = ConnectionState::Play;
+        connection
+    }
+
+    fn serverbound<F: FnOnce(&mut PacketWriter)>(packet_type: PacketType, write: F) -> Packet {
+        let mut data = PacketWriter::create(64);
+        write(&mut data);
+
+        Packet { data: data.as_ref().to_vec(), raw_size: 0, packet_type }
+    }
+
+    fn confirm_teleport(teleport_id: i32) -> Packet {
+        serverbound(PacketType::PlayServerboundConfirmTeleportation, |packet| packet.write_var_int(teleport_id))
+    }
+
+    fn move_and_look(x: f64, yaw: f32) -> Packet {
+        serverbound(PacketType::PlayServerboundSetPlayerPositionAndRotation, |packet| {
+            packet.write_vec3d(x, 64.0, 0.0);
+            packet.write_float(yaw);
+            packet.write_float(10.0);
+            packet.write_boolean(true);
+        })
+    }
+
+    #[tokio::test]
+    async fn teleports_sent_before_either_is_confirmed_are_both_accepted() {
+        let mut connection = playing_connection();
+        connection.teleport((1.0, 64.0, 1.0));
+        connection.teleport((2.0, 64.0, 2.0));
+        assert_eq!(connection.pending_teleports, [1, 2]);
+
+        connection.handle_packet(confirm_teleport(1)).await.unwrap();
+        assert_eq!(connection.pending_teleports, [2]);
+        connection.handle_packet(confirm_teleport(2)).await.unwrap();
+        assert!(connection.pending_teleports.is_empty());
+
+        // a late confirmation changes nothing
+        connection.handle_packet(confirm_teleport(1)).await.unwrap();
+        assert_eq!(connection.state, ConnectionState::Play);
+    }
+
+    #[tokio::test]
+    async fn confirming_a_teleport_drops_the_ones_before_it() {
+        let mut connection = playing_connection();
+        connection.teleport((1.0, 64.0, 1.0));
+        connection.teleport((2.0, 64.0, 2.0));
+        connection.teleport((3.0, 64.0, 3.0));
+
+        connection.handle_packet(confirm_teleport(2)).await.unwrap();
+        assert_eq!(connection.pending_teleports, [3]);
+    }
+
+    #[tokio::test]
+    async fn movement_is_ignored_until_teleports_are_confirmed() {
+        let mut connection = playing_connection();
+        connection.teleport((1.0, 64.0, 1.0));
+
+        connection.handle_packet(move_and_look(5.0, 90.0)).await.unwrap();
+        connection.handle_packet(serverbound(PacketType::PlayServerboundSetPlayerRotation, |packet| {
+            packet.write_float(45.0);
+            packet.write_float(0.0);
+            packet.write_boolean(true);
+        })).await.unwrap();
+        assert_eq!(connection.position, (1.0, 64.0, 1.0));
+        assert_eq!(connection.rotation, (0.0, 0.0));
+
+        connection.handle_packet(confirm_teleport(1)).await.unwrap();
+        connection.handle_packet(move_and_look(5.0, 90.0)).await.unwrap();
+        assert_eq!(connection.position, (5.0, 64.0, 0.0));
+        assert_eq!(connection.rotation, (90.0, 10.0));
+
+        connection.handle_packet(serverbound(PacketType::PlayServerboundSetPlayerRotation, |packet| {
+            packet.write_float(45.0);
+            packet.write_float(-20.0);
+            packet.write_boolean(true);
+        })).await.unwrap();
+        assert_eq!(connection.position, (5.0, 64.0, 0.0));
+        assert_eq!(connection.rotation, (45.0, -20.0));
+    }
+
+    #[tokio::test]
+    async fn many_packets_in_one_write_are_all_handled() {
+        let (mut client, _) = TestClient::connect(test_config());
+        client.handshake(client.protocol.as_i32(), 1).await;
+
+        let mut pings = Vec::new();
+        for payload in 0..1000i64 {
+            let ping = serverbound(PacketType::StatusServerboundPing, |packet| StatusPing { payload }.encode(packet));
+            pings.extend(ping.encode(client.protocol).unwrap());
+        }
+        assert!(pings.len() > 2 * COMPACT_THRESHOLD);
+        client.send_raw(&pings).await;
+
+        for payload in 0..1000i64 {
+            let pong = client.expect(PacketType::StatusClientboundPong).await;
+            assert_eq!(PacketReader::create(&pong).read_long().unwrap(), payload);
+        }
+
+        client.finish().await;
+    }
+
+    #[tokio::test]
+    async fn parsed_bytes_are_only_compacted_once_they_add_up() {
+        let mut connection = playing_connection();
+        connection.current_packet = (0..=255u8).cycle().take(5000).collect();
+
+        connection.consume(100);
+        assert_eq!((connection.read_offset, connection.current_packet.len()), (100, 5000));
+        assert_eq!(connection.unparsed()[0], 100);
+
+        connection.consume(4000);
+        assert_eq!((connection.read_offset, connection.current_packet.len()), (0, 900));
+        assert_eq!(connection.unparsed()[0], (4100 % 256) as u8);
+
+        connection.consume(900);
+        assert_eq!((connection.read_offset, connection.current_packet.len()), (0, 0));
+
+        // past the threshold, but less than what's left to parse
+        connection.current_packet = vec![0; 20000];
+        connection.consume(5000);
+        assert_eq!((connection.read_offset, connection.current_packet.len()), (5000, 20000));
+
+        connection.consume(5000);
+        assert_eq!((connection.read_offset, connection.current_packet.len()), (0, 10000));
+    }
+
+    #[tokio::test]
+    async fn connections_end_when_the_stream_does() {
+        let (mut client, server) = TestClient::connect(test_config());
+        client.login("Steve").await;
+        assert_eq!(server.online_players(), 1);
+
+        // half of a frame, then the end of the stream
+        client.send_raw(&[10, 0x12, 0]).await;
+        let TestClient { stream, connection, .. } = client;
+        drop(stream);
+
+        tokio::time::timeout(RECEIVE_TIMEOUT, connection).await.unwrap().unwrap();
+        assert_eq!(server.online_players(), 0);
+    }
+
+    #[tokio::test]
+    async fn status_pings_are_answered_end_to_end() {
+        let (mut client, _) = TestClient::connect(test_config());
+
+        // crafted by hand, so the framing isn't checked against itself
+        let mut bytes = vec![16, 0x00, 0xFA, 0x05, 9];
+        bytes.extend_from_slice(b"localhost");
+        bytes.extend_from_slice(&[0x63, 0xDD, 1]);
+        bytes.extend_from_slice(&[1, 0x00]);
+        bytes.extend_from_slice(&[9, 0x01, 0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0xCD, 0xEF]);
+        client.send_raw(&bytes).await;
+
+        let response = client.expect(PacketType::StatusClientboundResponse).await;
+        let json: serde_json::Value = serde_json::from_str(&PacketReader::create(&response).read_string(32767).unwrap()).unwrap();
+        assert_eq!(json["version"]["protocol"], 762);
+        assert!(json["players"]["max"].is_number() && json["players"]["online"].is_number(), "{}", json);
+        assert!(json.get("description").is_some(), "{}", json);
+
+        let pong = client.expect(PacketType::StatusClientboundPong).await;
+        assert_eq!(pong, [0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0xCD, 0xEF]);
+
+        client.finish().await;
+    }
+
+    #[tokio::test]
+    async fn logins_succeed_with_the_offline_uuid() {
+        let (mut client, _) = TestClient::connect(test_config());
+
+        let success = client.login("Steve").await;
+        let mut reader = PacketReader::create(&success);
+        assert_eq!(reader.read_uuid().unwrap(), offline_uuid("Steve"));
+        assert_eq!(reader.read_string(16).unwrap(), "Steve");
+        assert_eq!(reader.read_varint().unwrap(), 0);
+
+        client.finish().await;
+    }
+
+    #[tokio::test]
+    async fn malformed_frames_are_skipped_instead_of_waited_on() {
+        for malformed in [&[0x00][..], &[0x01, 0x80][..]] {
+            let (mut client, _) = TestClient::connect(test_config());
+            client.handshake(client.protocol.as_i32(), 1).await;
+
+            let request = serverbound(PacketType::StatusServerboundRequest, |_| {}).encode(client.protocol).unwrap();
+            client.send_raw(&[malformed, &request].concat()).await;
+
+            client.expect(PacketType::StatusClientboundResponse).await;
+            client.finish().await;
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn status_responses_wait_for_the_simulated_latency() {
+        let latency = SimulatedLatency { delay: Duration::from_millis(200), jitter: Duration::ZERO };
+        let (mut client, _) = TestClient::connect(Config { simulated_latency: latency, ..test_config() });
+        client.handshake(client.protocol.as_i32(), 1).await;
+        client.send(PacketType::StatusServerboundRequest, |_| {}).await;
+
+        // lets the connection handle the request and queue its answer, without time passing
+        for _ in 0..100 {
+            tokio::task::yield_now().await;
+        }
+        let mut byte = [0; 1];
+        assert!(tokio::time::timeout(Duration::ZERO, client.stream.read(&mut byte)).await.is_err());
+
+        tokio::time::advance(latency.delay).await;
+        client.expect(PacketType::StatusClientboundResponse).await;
+        client.finish().await;
     }
 }
@@ -0,0 +1,144 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use aes::Aes128;
+use cfb8::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use rsa::{Pkcs1v15Encrypt, RsaPrivateKey, RsaPublicKey};
+use rsa::pkcs8::EncodePublicKey;
+
+// the vanilla server uses 1024 bit keys and clients expect them
+const KEY_BITS: usize = 1024;
+
+#[derive(Debug)]
+pub enum EncryptionError {
+    KeyGeneration(rsa::Error),
+    KeyEncoding(rsa::pkcs8::spki::Error),
+    DecryptionFailed,
+    InvalidSharedSecret,
+    VerifyTokenMismatch,
+}
+
+impl Display for EncryptionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self, f)
+    }
+}
+
+impl Error for EncryptionError {}
+
+pub struct ServerKey {
+    private_key: RsaPrivateKey,
+    public_key_der: Vec<u8>,
+}
+
+impl ServerKey {
+    pub fn generate() -> Result<ServerKey, EncryptionError> {
+        let private_key = RsaPrivateKey::new(&mut OsRng, KEY_BITS).map_err(EncryptionError::KeyGeneration)?;
+        let public_key_der = RsaPublicKey::from(&private_key)
+            .to_public_key_der()
+            .map_err(EncryptionError::KeyEncoding)?
+            .into_vec();
+
+        Ok(ServerKey { private_key, public_key_der })
+    }
+
+    pub fn public_key_der(&self) -> &[u8] {
+        &self.public_key_der
+    }
+
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        self.private_key.decrypt(Pkcs1v15Encrypt, data).map_err(|_| EncryptionError::DecryptionFailed)
+    }
+}
+
+pub fn generate_verify_token() -> [u8; 4] {
+    let mut token = [0u8; 4];
+    OsRng.fill_bytes(&mut token);
+    token
+}
+
+// AES-128 in CFB8 mode, with the shared secret used as both key and iv
+pub struct StreamCipher {
+    encryptor: cfb8::Encryptor<Aes128>,
+    decryptor: cfb8::Decryptor<Aes128>,
+}
+
+impl StreamCipher {
+    pub fn create(shared_secret: &[u8]) -> Result<StreamCipher, EncryptionError> {
+        if shared_secret.len() != 16 {
+            return Err(EncryptionError::InvalidSharedSecret);
+        }
+
+        Ok(StreamCipher {
+            encryptor: cfb8::Encryptor::new_from_slices(shared_secret, shared_secret).map_err(|_| EncryptionError::InvalidSharedSecret)?,
+            decryptor: cfb8::Decryptor::new_from_slices(shared_secret, shared_secret).map_err(|_| EncryptionError::InvalidSharedSecret)?,
+        })
+    }
+
+    pub fn encrypt(&mut self, data: &mut [u8]) {
+        for byte in data.chunks_mut(1) {
+            self.encryptor.encrypt_block_mut(byte.into());
+        }
+    }
+
+    pub fn decrypt(&mut self, data: &mut [u8]) {
+        for byte in data.chunks_mut(1) {
+            self.decryptor.decrypt_block_mut(byte.into());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rsa::pkcs8::DecodePublicKey;
+
+    use super::*;
+
+    const SECRET: [u8; 16] = *b"0123456789abcdef";
+
+    #[test]
+    fn decrypting_reverses_encrypting() {
+        let plain: Vec<u8> = (0..=255).collect();
+
+        let mut data = plain.clone();
+        StreamCipher::create(&SECRET).unwrap().encrypt(&mut data);
+        assert_ne!(data, plain);
+
+        StreamCipher::create(&SECRET).unwrap().decrypt(&mut data);
+        assert_eq!(data, plain);
+    }
+
+    #[test]
+    fn the_stream_continues_across_calls() {
+        let plain: Vec<u8> = (0..100).collect();
+
+        let mut at_once = plain.clone();
+        StreamCipher::create(&SECRET).unwrap().encrypt(&mut at_once);
+
+        let mut in_pieces = plain.clone();
+        let mut cipher = StreamCipher::create(&SECRET).unwrap();
+        for piece in in_pieces.chunks_mut(7) {
+            cipher.encrypt(piece);
+        }
+
+        assert_eq!(in_pieces, at_once);
+    }
+
+    #[test]
+    fn secrets_of_the_wrong_length_are_rejected() {
+        assert!(matches!(StreamCipher::create(&SECRET[..15]), Err(EncryptionError::InvalidSharedSecret)));
+        assert!(matches!(StreamCipher::create(&[0; 32]), Err(EncryptionError::InvalidSharedSecret)));
+    }
+
+    #[test]
+    fn secrets_encrypted_with_the_public_key_are_decrypted() {
+        let key = ServerKey::generate().unwrap();
+        let public_key = RsaPublicKey::from_public_key_der(key.public_key_der()).unwrap();
+
+        let encrypted = public_key.encrypt(&mut OsRng, Pkcs1v15Encrypt, &SECRET).unwrap();
+        assert_eq!(key.decrypt(&encrypted).unwrap(), SECRET);
+        assert!(matches!(key.decrypt(&[0; 128]), Err(EncryptionError::DecryptionFailed)));
+    }
+}
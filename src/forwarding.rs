@@ -0,0 +1,141 @@
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::net::{IpAddr, SocketAddr};
+
+use uuid::Uuid;
+
+use crate::auth::ProfileProperty;
+
+#[derive(Debug)]
+pub enum ForwardingError {
+    MissingFields,
+    InvalidAddress,
+    InvalidUuid,
+    InvalidProperties,
+}
+
+impl Display for ForwardingError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        (self as &dyn Debug).fmt(f)
+    }
+}
+
+impl Error for ForwardingError {}
+
+// start of every PROXY protocol v2 header
+const PROXY_PROTOCOL_SIGNATURE: [u8; 12] = [0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A];
+
+// client details a BungeeCord proxy appends to the handshake host
+#[derive(Clone, Debug)]
+pub struct ForwardedClient {
+    pub address: IpAddr,
+    pub uuid: Uuid,
+    // the profile properties of the player, like their skin, empty when not forwarded
+    pub properties: Vec<ProfileProperty>,
+}
+
+// splits "host\0address\0uuid[\0properties]" into the host and the forwarded client
+pub fn parse_bungeecord_host(host: &str) -> Result<(&str, ForwardedClient), ForwardingError> {
+    let mut parts = host.split('\0');
+    let hostname = parts.next().unwrap_or_default();
+
+    let address = parts.next()
+        .ok_or(ForwardingError::MissingFields)?
+        .parse()
+        .map_err(|_| ForwardingError::InvalidAddress)?;
+
+    let uuid = Uuid::try_parse(parts.next().ok_or(ForwardingError::MissingFields)?)
+        .map_err(|_| ForwardingError::InvalidUuid)?;
+
+    let properties = match parts.next() {
+        Some(properties) => serde_json::from_str(properties).map_err(|_| ForwardingError::InvalidProperties)?,
+        None => Vec::new()
+    };
+
+    Ok((hostname, ForwardedClient { address, uuid, properties }))
+}
+
+// whether the host carries forwarding data, as opposed to markers like Forge's "\0FML2\0"
+pub fn has_forwarding_data(host: &str) -> bool {
+    let mut parts = host.split('\0');
+    parts.next();
+
+    matches!(parts.next().map(|address| address.parse::<IpAddr>()), Some(Ok(_)))
+}
+
+// a PROXY protocol v2 header telling the backend that a TCP connection from source reached
+// destination, mixed address families are both written as ipv6
+pub fn write_proxy_protocol_header(out: &mut Vec<u8>, source: SocketAddr, destination: SocketAddr) {
+    out.extend_from_slice(&PROXY_PROTOCOL_SIGNATURE);
+    out.push(0x21); // version 2, proxied connection
+
+    match (source.ip(), destination.ip()) {
+        (IpAddr::V4(source_ip), IpAddr::V4(destination_ip)) => {
+            out.push(0x11); // tcp over ipv4
+            out.extend_from_slice(&12u16.to_be_bytes());
+            out.extend_from_slice(&source_ip.octets());
+            out.extend_from_slice(&destination_ip.octets());
+        }
+        (source_ip, destination_ip) => {
+            out.push(0x21); // tcp over ipv6
+            out.extend_from_slice(&36u16.to_be_bytes());
+            out.extend_from_slice(&to_ipv6(source_ip).octets());
+            out.extend_from_slice(&to_ipv6(destination_ip).octets());
+        }
+    }
+
+    out.extend_from_slice(&source.port().to_be_bytes());
+    out.extend_from_slice(&destination.port().to_be_bytes());
+}
+
+fn to_ipv6(address: IpAddr) -> std::net::Ipv6Addr {
+    match address {
+        IpAddr::V4(address) => address.to_ipv6_mapped(),
+        IpAddr::V6(address) => address
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID: &str = "069a79f444e94726a5befca90e38aaf5";
+
+    #[test]
+    fn bungeecord_hosts_are_split_into_the_host_and_client() {
+        let host = format!("play.example.com\u{0}203.0.113.7\u{0}{}", UUID);
+        let (hostname, client) = parse_bungeecord_host(&host).unwrap();
+
+        assert_eq!(hostname, "play.example.com");
+        assert_eq!(client.address, "203.0.113.7".parse::<IpAddr>().unwrap());
+        assert_eq!(client.uuid, Uuid::parse_str(UUID).unwrap());
+        assert!(client.properties.is_empty());
+    }
+
+    #[test]
+    fn forwarded_properties_are_parsed() {
+        let host = format!("play.example.com\u{0}::1\u{0}{}\u{0}[{{\"name\":\"textures\",\"value\":\"e30=\",\"signature\":\"c2ln\"}}]", UUID);
+        let (_, client) = parse_bungeecord_host(&host).unwrap();
+
+        assert_eq!(client.address, "::1".parse::<IpAddr>().unwrap());
+        assert_eq!(client.properties.len(), 1);
+        assert_eq!(client.properties[0].name, "textures");
+        assert_eq!(client.properties[0].signature.as_deref(), Some("c2ln"));
+    }
+
+    #[test]
+    fn invalid_forwarding_data_is_rejected() {
+        assert!(matches!(parse_bungeecord_host("play.example.com"), Err(ForwardingError::MissingFields)));
+        assert!(matches!(parse_bungeecord_host("play.example.com\u{0}203.0.113.7"), Err(ForwardingError::MissingFields)));
+        assert!(matches!(parse_bungeecord_host(&format!("play.example.com\u{0}nowhere\u{0}{}", UUID)), Err(ForwardingError::InvalidAddress)));
+        assert!(matches!(parse_bungeecord_host("play.example.com\u{0}203.0.113.7\u{0}steve"), Err(ForwardingError::InvalidUuid)));
+        assert!(matches!(parse_bungeecord_host(&format!("play.example.com\u{0}203.0.113.7\u{0}{}\u{0}{{", UUID)), Err(ForwardingError::InvalidProperties)));
+    }
+
+    #[test]
+    fn forge_markers_are_not_forwarding_data() {
+        assert!(has_forwarding_data(&format!("play.example.com\u{0}203.0.113.7\u{0}{}", UUID)));
+        assert!(!has_forwarding_data("play.example.com\u{0}FML2\u{0}"));
+        assert!(!has_forwarding_data("play.example.com"));
+    }
+}
@@ -0,0 +1,117 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::Notify;
+
+use crate::outbound::PacketSender;
+use crate::packet::{PacketType, PacketWriter};
+use crate::protocol::ProtocolVersion;
+
+pub const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+pub const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// the keep-alive a client still has to answer, shared between the connection and the task
+// sending them
+pub struct KeepAlive {
+    pending: Mutex<Option<(i64, Instant)>>,
+    timed_out: Notify,
+}
+
+impl KeepAlive {
+    pub fn create() -> Arc<KeepAlive> {
+        Arc::new(KeepAlive {
+            pending: Mutex::new(None),
+            timed_out: Notify::new(),
+        })
+    }
+
+    // returns false when the id isn't the one of the last keep-alive sent
+    pub fn acknowledge(&self, id: i64) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+
+        match *pending {
+            Some((pending_id, _)) if pending_id == id => {
+                *pending = None;
+                true
+            }
+            _ => false
+        }
+    }
+
+    pub async fn timed_out(&self) {
+        self.timed_out.notified().await
+    }
+
+    // sends a keep-alive every interval until the connection closes or the client stops answering
+    pub async fn run(self: Arc<Self>, sender: PacketSender, protocol: ProtocolVersion) {
+        let mut interval = tokio::time::interval(KEEP_ALIVE_INTERVAL);
+
+        loop {
+            interval.tick().await;
+            if sender.is_closed() {
+                return;
+            }
+
+            let id = {
+                let mut pending = self.pending.lock().unwrap();
+
+                if let Some((_, sent_at)) = *pending {
+                    if sent_at.elapsed() >= KEEP_ALIVE_TIMEOUT {
+                        self.timed_out.notify_one();
+                        return;
+                    }
+
+                    continue;
+                }
+
+                let id = rand::random::<i64>();
+                *pending = Some((id, Instant::now()));
+                id
+            };
+
+            let mut packet = PacketWriter::create(9);
+            packet.write_packet_type(PacketType::PlayClientboundKeepAlive, protocol);
+            packet.write_long(id);
+
+            if sender.send(packet).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::outbound::Outbound;
+    use crate::packet::PacketReader;
+
+    #[test]
+    fn nothing_is_acknowledged_before_a_keep_alive_was_sent() {
+        assert!(!KeepAlive::create().acknowledge(0));
+    }
+
+    #[tokio::test]
+    async fn only_the_id_of_the_keep_alive_sent_is_acknowledged_once() {
+        let keep_alive = KeepAlive::create();
+        let (sender, mut queue) = mpsc::channel(1);
+        let task = tokio::spawn(keep_alive.clone().run(PacketSender::create(sender), ProtocolVersion::V1_19_4));
+
+        // the first one is sent right away
+        let packet = match queue.recv().await {
+            Some(Outbound::Packet(packet)) => packet,
+            _ => panic!("expected a keep-alive")
+        };
+        let mut reader = PacketReader::create(packet.as_ref());
+        assert_eq!(reader.read_varint().unwrap(), 0x23);
+        let id = reader.read_long().unwrap();
+
+        assert!(!keep_alive.acknowledge(id.wrapping_add(1)));
+        assert!(keep_alive.acknowledge(id));
+        assert!(!keep_alive.acknowledge(id));
+
+        task.abort();
+    }
+}
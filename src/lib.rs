@@ -0,0 +1,20 @@
+pub mod auth;
+pub mod bans;
+pub mod config;
+pub mod connection;
+pub mod encryption;
+pub mod forwarding;
+pub mod keep_alive;
+pub mod middleware;
+pub mod nbt;
+pub mod outbound;
+pub mod packet;
+pub mod protocol;
+pub mod query;
+pub mod registry;
+pub mod rate_limit;
+pub mod router;
+pub mod server;
+pub mod status;
+pub mod stream;
+pub mod whitelist;
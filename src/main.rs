@@ -1,17 +1,282 @@
-use tokio::net::TcpListener;
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+use std::sync::Arc;
+use std::time::Duration;
 
-mod connection;
-mod packet;
+use socket2::{SockRef, TcpKeepalive};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::sync::{mpsc, OwnedSemaphorePermit};
+use tokio::task::JoinSet;
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::EnvFilter;
+
+use funny_proxy::config::Config;
+use funny_proxy::connection;
+use funny_proxy::query::QueryServer;
+use funny_proxy::router::Router;
+use funny_proxy::server::{load_bans, load_whitelist, Server};
+use funny_proxy::stream::ClientStream;
+#[cfg(unix)]
+use funny_proxy::stream::UNIX_PEER;
+
+// a connection from one of the listeners, with the slot it takes up
+type Accepted = (ClientStream, SocketAddr, OwnedSemaphorePermit);
+
+const CONFIG_PATH: &str = "config.toml";
+
+// time connections get to say goodbye to their clients on ctrl+c
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(5);
 
 #[tokio::main]
 async fn main() {
-    let listener = TcpListener::bind("127.0.0.1:25565").await.unwrap();
+    // RUST_LOG=debug also logs every packet
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .init();
+
+    let config = match Config::load(CONFIG_PATH) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("failed to load {}: {}", CONFIG_PATH, e);
+            std::process::exit(1);
+        }
+    };
+
+    let server = Arc::new(Server::create(config));
+
+    // a failing address is skipped as long as another one could be bound
+    let mut listeners = Vec::new();
+    for address in &server.config.bind {
+        match TcpListener::bind(address).await {
+            Ok(listener) => {
+                info!("listening on {}", address);
+                listeners.push(listener);
+            }
+            Err(e) => error!("failed to bind {}: {}", address, e)
+        }
+    }
+
+    #[cfg(unix)]
+    let unix_listener = match &server.config.unix_socket {
+        Some(path) => {
+            // a socket file left behind by a previous run would make binding fail, anything else at
+            // the path is left alone and fails the bind instead
+            if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+                let _ = std::fs::remove_file(path);
+            }
+            match UnixListener::bind(path) {
+                Ok(listener) => {
+                    info!("listening on {}", path.display());
+                    Some(listener)
+                }
+                Err(e) => {
+                    error!("failed to bind {}: {}", path.display(), e);
+                    None
+                }
+            }
+        }
+        None => None
+    };
+    #[cfg(not(unix))]
+    if server.config.unix_socket.is_some() {
+        warn!("unix sockets are not supported on this platform");
+    }
+
+    if listeners.is_empty() {
+        error!("no address to listen on");
+        std::process::exit(1);
+    }
+
+    if let Some(query_address) = server.config.query_address {
+        // the server list ping still works without it, so it's not worth stopping over
+        match UdpSocket::bind(query_address).await {
+            Ok(socket) => {
+                let mut query = QueryServer::create(socket, listeners[0].local_addr().unwrap(), server.config.clone());
+
+                tokio::spawn(async move {
+                    query.run().await;
+                });
+            }
+            Err(e) => error!("failed to bind the query socket to {}, query is disabled: {}", query_address, e)
+        }
+    }
+
+    #[cfg(unix)]
+    tokio::spawn(reload_on_hangup(server.clone()));
+    #[cfg(unix)]
+    tokio::spawn(toggle_maintenance_on_signal(server.clone()));
+
+    let initializing = server.clone();
+    tokio::task::spawn_blocking(move || {
+        initializing.initialize();
+        info!("server is ready");
+    });
+
+    // each listener accepts on its own task and hands the sockets over to this one
+    let (accepted_sender, mut accepted) = mpsc::channel(1);
+    let mut accept_loops = JoinSet::new();
+    for listener in listeners {
+        accept_loops.spawn(accept_loop(listener, server.clone(), accepted_sender.clone()));
+    }
+    #[cfg(unix)]
+    if let Some(listener) = unix_listener {
+        accept_loops.spawn(accept_unix_loop(listener, server.clone(), accepted_sender.clone()));
+    }
+
+    let mut connections = JoinSet::new();
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+
+    loop {
+        tokio::select! {
+            Some((socket, peer, permit)) = accepted.recv() => {
+                let server = server.clone();
+
+                connections.spawn(async move {
+                    let local = match socket.local_addr() {
+                        Ok(local) => local,
+                        Err(e) => {
+                            warn!("failed to set up the connection of {}: {}", peer, e);
+                            return;
+                        }
+                    };
+
+                    let peer_name = socket.describe_peer(peer);
+                    connection::Connection::create(socket, peer, local, server).with_peer_name(peer_name).process().await;
+                    drop(permit);
+                });
+            }
+            // forgets about finished connections so the set doesn't keep growing
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            _ = &mut ctrl_c => break
+        }
+    }
+
+    info!("shutting down, closing {} connections", server.open_connections());
+    // closes the listeners
+    accept_loops.shutdown().await;
+    server.shutdown();
+
+    // proxied connections can't be told about it and are only closed once the grace period is over
+    let drained = tokio::time::timeout(SHUTDOWN_GRACE_PERIOD, async {
+        while connections.join_next().await.is_some() {}
+    }).await;
 
+    if drained.is_err() {
+        warn!("closing {} connections that are still open", connections.len());
+    }
+}
+
+// waits for a free connection slot before accepting, so that further connections queue up in
+// the listen backlog rather than in memory
+async fn accept_loop(listener: TcpListener, server: Arc<Server>, accepted: mpsc::Sender<Accepted>) {
+    loop {
+        let permit = server.connection_permits.clone().acquire_owned().await.unwrap();
+
+        let (socket, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("failed to accept a connection on {}: {}", listener.local_addr().unwrap(), e);
+                continue;
+            }
+        };
+
+        if let Err(e) = configure_socket(&socket, server.config.tcp_keepalive) {
+            warn!("failed to configure the socket of {}: {}", peer, e);
+        }
+
+        if let Some(ban) = server.bans().ip_ban(peer.ip()) {
+            debug!("refusing banned address {} ({})", peer.ip(), ban.reason.as_deref().unwrap_or("no reason"));
+            continue;
+        }
+
+        if !server.rate_limiter.allow(peer.ip()) {
+            continue;
+        }
+
+        if accepted.send((ClientStream::Tcp(socket), peer, permit)).await.is_err() {
+            return;
+        }
+    }
+}
+
+// small packets are sent right away instead of being held back to coalesce with later ones
+fn configure_socket(socket: &TcpStream, keepalive: Duration) -> std::io::Result<()> {
+    socket.set_nodelay(true)?;
+
+    if !keepalive.is_zero() {
+        let keepalive = TcpKeepalive::new().with_time(keepalive).with_interval(keepalive);
+        SockRef::from(socket).set_tcp_keepalive(&keepalive)?;
+    }
+
+    Ok(())
+}
+
+// clients of the unix socket are on the same host, so they aren't rate limited
+#[cfg(unix)]
+async fn accept_unix_loop(listener: UnixListener, server: Arc<Server>, accepted: mpsc::Sender<Accepted>) {
     loop {
-        let (socket, _) = listener.accept().await.unwrap();
+        let permit = server.connection_permits.clone().acquire_owned().await.unwrap();
+
+        let socket = match listener.accept().await {
+            Ok((socket, _)) => socket,
+            Err(e) => {
+                warn!("failed to accept a connection on the unix socket: {}", e);
+                continue;
+            }
+        };
+
+        if accepted.send((ClientStream::Unix(socket), UNIX_PEER, permit)).await.is_err() {
+            return;
+        }
+    }
+}
+
+// swaps in the routes, the whitelist and bans from the config file whenever the process receives SIGHUP
+#[cfg(unix)]
+async fn reload_on_hangup(server: Arc<Server>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            error!("failed to listen for SIGHUP: {}", e);
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        match Config::load(CONFIG_PATH) {
+            Ok(config) => {
+                server.set_router(Router::create(&config.routes, config.backend));
+                server.set_whitelist(load_whitelist(&config));
+                server.set_bans(load_bans(&config));
+                info!("reloaded routes, the whitelist and bans from {}", CONFIG_PATH);
+            }
+            Err(e) => error!("failed to reload {}: {}", CONFIG_PATH, e)
+        }
+    }
+}
+
+// switches maintenance mode on and off whenever the process receives SIGUSR1
+#[cfg(unix)]
+async fn toggle_maintenance_on_signal(server: Arc<Server>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut user_defined = match signal(SignalKind::user_defined1()) {
+        Ok(user_defined) => user_defined,
+        Err(e) => {
+            error!("failed to listen for SIGUSR1: {}", e);
+            return;
+        }
+    };
 
-        tokio::spawn(async move {
-            connection::Connection::create(socket).process().await;
-        });
+    while user_defined.recv().await.is_some() {
+        let enabled = !server.in_maintenance();
+        server.set_maintenance(enabled);
+        info!("maintenance mode {}", if enabled { "enabled" } else { "disabled" });
     }
 }
@@ -0,0 +1,168 @@
+use std::io;
+use std::sync::Mutex;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tracing::debug;
+
+use crate::connection::ConnectionState;
+use crate::packet::{Direction, MAX_PACKET_LENGTH, Packet, PacketReader, PacketType};
+use crate::protocol::ProtocolVersion;
+
+pub enum Verdict {
+    Pass,
+    // forwards this packet in place of the one received
+    Replace(Packet),
+    Drop,
+}
+
+// sees every packet of a known type a proxied connection forwards after the handshake, until the
+// connection is encrypted. frames that can't be decoded are forwarded as they are
+pub trait PacketMiddleware: Send + Sync {
+    fn on_packet(&self, direction: Direction, packet: &Packet) -> Verdict;
+}
+
+// what both directions of a proxied connection need to take its frames apart, which the packets
+// from the backend change
+pub(crate) struct ProxiedProtocol {
+    protocol: ProtocolVersion,
+    // the state and the compression threshold
+    framing: Mutex<(ConnectionState, Option<usize>)>,
+}
+
+impl ProxiedProtocol {
+    pub(crate) fn create(protocol: ProtocolVersion, state: ConnectionState) -> ProxiedProtocol {
+        ProxiedProtocol {
+            protocol,
+            framing: Mutex::new((state, None)),
+        }
+    }
+
+    fn framing(&self) -> (ConnectionState, Option<usize>) {
+        *self.framing.lock().unwrap()
+    }
+
+    // follows the backend through the login, returning whether everything after the packet is encrypted
+    fn track(&self, packet: &Packet) -> bool {
+        let mut framing = self.framing.lock().unwrap();
+
+        match packet.packet_type {
+            PacketType::LoginClientboundSetCompression => {
+                let threshold = PacketReader::create(&packet.data).read_varint().unwrap_or(-1);
+                framing.1 = if threshold >= 0 { Some(threshold as usize) } else { None };
+            }
+            PacketType::LoginClientboundSuccess => framing.0 = ConnectionState::Play,
+            // the backend's next packets and the client's after its response are encrypted
+            PacketType::LoginClientboundEncryptionRequest | PacketType::LoginServerboundEncryptionResponse => return true,
+            _ => {}
+        }
+
+        false
+    }
+}
+
+// copies from one side of a proxied connection to the other a frame at a time, letting the
+// middleware drop or replace them. pending is what was already read from the source. returns the
+// bytes read once the source closes
+pub(crate) async fn forward_filtered<R, W>(
+    source: &mut R,
+    destination: &mut W,
+    pending: &[u8],
+    direction: Direction,
+    middleware: &dyn PacketMiddleware,
+    proxied: &ProxiedProtocol,
+) -> io::Result<u64>
+    where R: AsyncRead + Unpin, W: AsyncWrite + Unpin {
+    let mut buffer = pending.to_vec();
+    let mut received = 0;
+    let mut encrypted = false;
+
+    loop {
+        let mut offset = 0;
+
+        while !encrypted {
+            let frame_size = match next_frame_size(&buffer[offset..])? {
+                Some(frame_size) => frame_size,
+                None => break
+            };
+
+            let frame = &buffer[offset..offset + frame_size];
+            offset += frame_size;
+            encrypted = forward_frame(frame, destination, direction, middleware, proxied).await?;
+        }
+
+        // nothing can be read from an encrypted connection, so the rest is only copied
+        if encrypted {
+            destination.write_all(&buffer[offset..]).await?;
+            offset = buffer.len();
+        }
+        buffer.drain(..offset);
+
+        let read = source.read_buf(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+
+        received += read as u64;
+    }
+
+    destination.shutdown().await?;
+    Ok(received)
+}
+
+// the size of the frame at the start of the buffer, None until all of it arrived
+fn next_frame_size(buffer: &[u8]) -> io::Result<Option<usize>> {
+    let (length, length_size) = match PacketReader::create(buffer).peek_varint() {
+        Ok(Some(length)) => length,
+        Ok(None) => return Ok(None),
+        Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e))
+    };
+
+    if !(0..=MAX_PACKET_LENGTH).contains(&length) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid frame length {}", length)));
+    }
+
+    let frame_size = length_size + length as usize;
+    Ok(if buffer.len() >= frame_size { Some(frame_size) } else { None })
+}
+
+// returns whether the connection is encrypted after the frame
+async fn forward_frame<W: AsyncWrite + Unpin>(
+    frame: &[u8],
+    destination: &mut W,
+    direction: Direction,
+    middleware: &dyn PacketMiddleware,
+    proxied: &ProxiedProtocol,
+) -> io::Result<bool> {
+    let (state, compression_threshold) = proxied.framing();
+    let decoded = match direction {
+        Direction::Serverbound => Packet::decode(frame, state, proxied.protocol, compression_threshold).await,
+        Direction::Clientbound => Packet::decode_clientbound(frame, state, proxied.protocol, compression_threshold).await,
+    };
+
+    let packet = match decoded {
+        Ok(packet) => packet,
+        Err(_) => {
+            destination.write_all(frame).await?;
+            return Ok(false);
+        }
+    };
+
+    let forwarded = match middleware.on_packet(direction, &packet) {
+        Verdict::Pass => {
+            destination.write_all(frame).await?;
+            packet
+        }
+        Verdict::Replace(replacement) => {
+            let bytes = replacement.encode_compressed(proxied.protocol, compression_threshold)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            destination.write_all(&bytes).await?;
+            replacement
+        }
+        Verdict::Drop => {
+            debug!("middleware dropped {:?} packet", packet.packet_type);
+            return Ok(false);
+        }
+    };
+
+    Ok(proxied.track(&forwarded))
+}
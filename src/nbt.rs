@@ -0,0 +1,106 @@
+// tag ids of the supported NBT types
+pub const TAG_END: u8 = 0;
+pub const TAG_BYTE: u8 = 1;
+pub const TAG_INT: u8 = 3;
+pub const TAG_LONG: u8 = 4;
+pub const TAG_FLOAT: u8 = 5;
+pub const TAG_DOUBLE: u8 = 6;
+pub const TAG_STRING: u8 = 8;
+pub const TAG_LIST: u8 = 9;
+pub const TAG_COMPOUND: u8 = 10;
+pub const TAG_LONG_ARRAY: u8 = 12;
+
+// writes uncompressed NBT. named tags go into compounds, the *_element functions write the
+// unnamed values of a list, which has to be given the type and number of its elements upfront.
+// compounds in a list are only their tags followed by end_compound
+pub struct NbtWriter {
+    buf: Vec<u8>,
+}
+
+impl NbtWriter {
+    pub fn create() -> NbtWriter {
+        NbtWriter { buf: Vec::new() }
+    }
+
+    // the root compound, with the empty name it has in packets
+    pub fn begin_root(&mut self) {
+        self.begin_compound("");
+    }
+
+    pub fn begin_compound(&mut self, name: &str) {
+        self.write_header(TAG_COMPOUND, name);
+    }
+
+    pub fn end_compound(&mut self) {
+        self.buf.push(TAG_END);
+    }
+
+    pub fn begin_list(&mut self, name: &str, element_type: u8, length: i32) {
+        self.write_header(TAG_LIST, name);
+        // empty lists are written with the end tag as their type
+        self.buf.push(if length == 0 { TAG_END } else { element_type });
+        self.buf.extend_from_slice(&length.to_be_bytes());
+    }
+
+    pub fn write_byte_tag(&mut self, name: &str, value: i8) {
+        self.write_header(TAG_BYTE, name);
+        self.buf.push(value as u8);
+    }
+
+    pub fn write_boolean_tag(&mut self, name: &str, value: bool) {
+        self.write_byte_tag(name, value as i8);
+    }
+
+    pub fn write_int_tag(&mut self, name: &str, value: i32) {
+        self.write_header(TAG_INT, name);
+        self.write_int_element(value);
+    }
+
+    pub fn write_int_element(&mut self, value: i32) {
+        self.buf.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn write_long_tag(&mut self, name: &str, value: i64) {
+        self.write_header(TAG_LONG, name);
+        self.buf.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn write_long_array_tag(&mut self, name: &str, values: &[i64]) {
+        self.write_header(TAG_LONG_ARRAY, name);
+        self.buf.extend_from_slice(&(values.len() as i32).to_be_bytes());
+        values.iter().for_each(|value| self.buf.extend_from_slice(&value.to_be_bytes()));
+    }
+
+    pub fn write_float_tag(&mut self, name: &str, value: f32) {
+        self.write_header(TAG_FLOAT, name);
+        self.buf.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn write_double_tag(&mut self, name: &str, value: f64) {
+        self.write_header(TAG_DOUBLE, name);
+        self.buf.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn write_string_tag(&mut self, name: &str, value: &str) {
+        self.write_header(TAG_STRING, name);
+        self.write_string_element(value);
+    }
+
+    // plain utf-8, which only differs from java's modified utf-8 for NUL and characters outside
+    // the basic multilingual plane
+    pub fn write_string_element(&mut self, value: &str) {
+        self.buf.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        self.buf.extend_from_slice(value.as_bytes());
+    }
+
+    fn write_header(&mut self, tag: u8, name: &str) {
+        self.buf.push(tag);
+        self.write_string_element(name);
+    }
+}
+
+impl AsRef<[u8]> for NbtWriter {
+    fn as_ref(&self) -> &[u8] {
+        &self.buf
+    }
+}
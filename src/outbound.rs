@@ -0,0 +1,233 @@
+use std::time::Duration;
+
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+use crate::config::SimulatedLatency;
+use crate::connection::ConnectionError;
+use crate::encryption::StreamCipher;
+use crate::packet::PacketWriter;
+
+// packets waiting to be written before senders have to wait
+pub const OUTBOUND_QUEUE_SIZE: usize = 256;
+
+pub(crate) enum Outbound {
+    Packet(PacketWriter),
+    // written with a single write
+    Batch(Vec<PacketWriter>),
+    // written as is, for the legacy ping
+    Raw(Vec<u8>),
+    // applies to the packets queued after it
+    EnableCompression(usize),
+    EnableEncryption(Box<StreamCipher>),
+    // stops the writer once everything before it is written
+    Close,
+}
+
+// lets other tasks queue packets for a connection
+#[derive(Clone)]
+pub struct PacketSender {
+    queue: mpsc::Sender<Outbound>,
+}
+
+impl PacketSender {
+    pub(crate) fn create(queue: mpsc::Sender<Outbound>) -> PacketSender {
+        PacketSender { queue }
+    }
+
+    pub async fn send(&self, packet: PacketWriter) -> Result<(), ConnectionError> {
+        self.queue.send(Outbound::Packet(packet)).await.map_err(|_| ConnectionError::Other("connection closed".into()))
+    }
+
+    // queues the packet unless the queue is full or closed, for packets that don't have to arrive
+    pub fn try_send(&self, packet: PacketWriter) -> bool {
+        self.queue.try_send(Outbound::Packet(packet)).is_ok()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.queue.is_closed()
+    }
+}
+
+// frames and writes the queued packets until the connection closes it, then hands back the
+// stream so it can be shut down or used for proxying
+pub(crate) async fn write_outbound<W: AsyncWrite + Unpin>(
+    mut stream: W,
+    mut queue: mpsc::Receiver<Outbound>,
+    write_timeout: Duration,
+    latency: SimulatedLatency,
+) -> Result<W, ConnectionError> {
+    let mut compression_threshold = None;
+    let mut cipher: Option<StreamCipher> = None;
+
+    while let Some(outbound) = queue.recv().await {
+        let packets = match outbound {
+            Outbound::Packet(packet) => vec![packet],
+            Outbound::Batch(packets) => packets,
+            Outbound::Raw(bytes) => {
+                write_with_timeout(&mut stream, &bytes, write_timeout).await?;
+                continue;
+            }
+            Outbound::EnableCompression(threshold) => {
+                compression_threshold = Some(threshold);
+                continue;
+            }
+            Outbound::EnableEncryption(encryption) => {
+                cipher = Some(*encryption);
+                continue;
+            }
+            Outbound::Close => break
+        };
+
+        let mut frames = PacketWriter::create(packets.iter().map(|packet| packet.len() + 10).sum());
+        for packet in &packets {
+            packet.write_frame(&mut frames, compression_threshold).map_err(|e| ConnectionError::Other(e.into()))?;
+        }
+
+        if let Some(cipher) = &mut cipher {
+            cipher.encrypt(frames.as_mut());
+        }
+
+        if latency.is_enabled() {
+            tokio::time::sleep(latency.sample()).await;
+        }
+
+        write_with_timeout(&mut stream, frames.as_ref(), write_timeout).await?;
+    }
+
+    Ok(stream)
+}
+
+async fn write_with_timeout<W: AsyncWrite + Unpin>(stream: &mut W, bytes: &[u8], write_timeout: Duration) -> Result<(), ConnectionError> {
+    match tokio::time::timeout(write_timeout, stream.write_all(bytes)).await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(e)) => Err(ConnectionError::Other(e.into())),
+        Err(_) => Err(ConnectionError::WriteTimeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    // a client that never reads, so nothing it's sent is ever accepted
+    struct StalledWriter;
+
+    impl AsyncWrite for StalledWriter {
+        fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, _buf: &[u8]) -> Poll<io::Result<usize>> {
+            Poll::Pending
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Pending
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Pending
+        }
+    }
+
+    #[tokio::test]
+    async fn stalled_writes_time_out() {
+        let (sender, queue) = mpsc::channel(OUTBOUND_QUEUE_SIZE);
+        let mut packet = PacketWriter::create(8);
+        packet.write_var_int(0);
+        sender.send(Outbound::Packet(packet)).await.unwrap();
+
+        let writer = write_outbound(StalledWriter, queue, Duration::from_millis(50), SimulatedLatency::default());
+        let result = tokio::time::timeout(Duration::from_secs(5), writer).await
+            .expect("the writer should give up on its own");
+
+        assert!(matches!(result, Err(ConnectionError::WriteTimeout)));
+    }
+
+    // keeps every write apart, to tell how many it took
+    #[derive(Default)]
+    struct RecordingWriter {
+        writes: Vec<Vec<u8>>,
+    }
+
+    impl AsyncWrite for RecordingWriter {
+        fn poll_write(mut self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+            self.writes.push(buf.to_vec());
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn packet(id: i32, size: usize) -> PacketWriter {
+        let mut packet = PacketWriter::create(size + 1);
+        packet.write_var_int(id);
+        std::io::Write::write_all(&mut packet, &vec![id as u8; size]).unwrap();
+        packet
+    }
+
+    #[tokio::test]
+    async fn batches_are_written_at_once_as_their_frames_one_after_another() {
+        for threshold in [None, Some(64)] {
+            let (sender, queue) = mpsc::channel(OUTBOUND_QUEUE_SIZE);
+            if let Some(threshold) = threshold {
+                sender.send(Outbound::EnableCompression(threshold)).await.unwrap();
+            }
+            sender.send(Outbound::Batch(vec![packet(0x28, 300), packet(0x0C, 2), packet(0x34, 9)])).await.unwrap();
+            sender.send(Outbound::Close).await.unwrap();
+
+            let writer = write_outbound(RecordingWriter::default(), queue, Duration::from_secs(5), SimulatedLatency::default()).await.unwrap();
+
+            let mut expected = PacketWriter::create(512);
+            for packet in [packet(0x28, 300), packet(0x0C, 2), packet(0x34, 9)] {
+                packet.write_frame(&mut expected, threshold).unwrap();
+            }
+
+            assert_eq!(writer.writes.len(), 1);
+            assert_eq!(writer.writes[0], expected.as_ref());
+        }
+    }
+
+    #[tokio::test]
+    async fn packets_from_other_tasks_are_written_until_the_writer_is_closed() {
+        let (sender, queue) = mpsc::channel(OUTBOUND_QUEUE_SIZE);
+        let packets = PacketSender::create(sender.clone());
+        let writer = tokio::spawn(write_outbound(RecordingWriter::default(), queue, Duration::from_secs(5), SimulatedLatency::default()));
+
+        let background = packets.clone();
+        tokio::spawn(async move { background.send(packet(0x23, 8)).await.unwrap() }).await.unwrap();
+        assert!(packets.try_send(packet(0x64, 3)));
+        sender.send(Outbound::Close).await.unwrap();
+
+        // the writer hands back the stream once everything before the close is written
+        let writer = writer.await.unwrap().unwrap();
+        let mut expected = PacketWriter::create(32);
+        packet(0x23, 8).write_frame(&mut expected, None).unwrap();
+        packet(0x64, 3).write_frame(&mut expected, None).unwrap();
+        assert_eq!(writer.writes.concat(), expected.as_ref());
+
+        assert!(packets.is_closed());
+        assert!(packets.send(packet(0x23, 8)).await.is_err());
+        assert!(!packets.try_send(packet(0x23, 8)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn packets_are_held_back_by_the_simulated_latency() {
+        let latency = SimulatedLatency { delay: Duration::from_millis(50), jitter: Duration::ZERO };
+        let (sender, queue) = mpsc::channel(OUTBOUND_QUEUE_SIZE);
+        sender.send(Outbound::Packet(packet(0x23, 8))).await.unwrap();
+        sender.send(Outbound::Close).await.unwrap();
+
+        let started = tokio::time::Instant::now();
+        let writer = write_outbound(RecordingWriter::default(), queue, Duration::from_secs(5), latency).await.unwrap();
+
+        assert_eq!(started.elapsed(), latency.delay);
+        assert_eq!(writer.writes.len(), 1);
+    }
+}
@@ -1,17 +1,33 @@
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
-use std::io::Write;
+use std::io::{Read, Write};
 use std::ops::Not;
 use std::str::Utf8Error;
 
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
 use lazy_static::lazy_static;
+use serde::Deserialize;
 use tokio::io::{AsyncWrite, AsyncWriteExt};
 use uuid::Uuid;
 
 use crate::connection::ConnectionState;
+use crate::protocol::ProtocolVersion;
 
-#[derive(Hash, PartialEq, Eq, Copy, Clone, Debug)]
+pub mod chunk;
+pub mod metadata;
+pub mod payload;
+pub mod player_info;
+
+// largest length a frame can declare, the vanilla limit of a 3 byte varint
+pub const MAX_PACKET_LENGTH: i32 = 2097151;
+
+// largest size a compressed packet may inflate to
+pub const MAX_UNCOMPRESSED_LENGTH: i32 = 8388608;
+
+#[derive(Hash, PartialEq, Eq, Copy, Clone, Debug, Deserialize)]
 pub enum PacketType {
     HandshakeServerboundStart,
     StatusServerboundRequest,
@@ -19,11 +35,113 @@ pub enum PacketType {
     StatusServerboundPing,
     StatusClientboundPong,
     LoginServerboundStart,
+    LoginClientboundEncryptionRequest,
+    LoginServerboundEncryptionResponse,
     LoginClientboundSuccess,
+    LoginClientboundDisconnect,
+    LoginClientboundSetCompression,
     PlayClientboundLogin,
     PlayClientboundDifficulty,
     PlayClientboundAbilities,
-    PlayClientboundSetDefaultSpawnPosition
+    PlayClientboundSetDefaultSpawnPosition,
+    PlayClientboundKeepAlive,
+    PlayClientboundDisconnect,
+    PlayClientboundPlayerInfoUpdate,
+    PlayClientboundChunkDataAndLight,
+    PlayClientboundSetCenterChunk,
+    PlayClientboundSynchronizePlayerPosition,
+    PlayClientboundSystemChatMessage,
+    PlayClientboundPluginMessage,
+    PlayClientboundPing,
+    PlayServerboundKeepAlive,
+    PlayServerboundConfirmTeleportation,
+    PlayServerboundMessageAcknowledgment,
+    PlayServerboundChatCommand,
+    PlayServerboundChatMessage,
+    PlayServerboundClientInformation,
+    PlayServerboundSetPlayerPosition,
+    PlayServerboundSetPlayerPositionAndRotation,
+    PlayServerboundSetPlayerRotation,
+    PlayServerboundClickContainer,
+    PlayServerboundCloseContainer,
+    PlayServerboundPlayerAction,
+    PlayServerboundUseItem,
+    PlayServerboundPong,
+    PlayServerboundPluginMessage,
+}
+
+#[derive(Hash, PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Direction {
+    Serverbound,
+    Clientbound,
+}
+
+impl PacketType {
+    pub fn direction(&self) -> Direction {
+        match self {
+            PacketType::HandshakeServerboundStart |
+            PacketType::StatusServerboundRequest |
+            PacketType::StatusServerboundPing |
+            PacketType::LoginServerboundStart |
+            PacketType::LoginServerboundEncryptionResponse |
+            PacketType::PlayServerboundConfirmTeleportation |
+            PacketType::PlayServerboundMessageAcknowledgment |
+            PacketType::PlayServerboundChatCommand |
+            PacketType::PlayServerboundChatMessage |
+            PacketType::PlayServerboundClientInformation |
+            PacketType::PlayServerboundSetPlayerPosition |
+            PacketType::PlayServerboundSetPlayerPositionAndRotation |
+            PacketType::PlayServerboundSetPlayerRotation |
+            PacketType::PlayServerboundKeepAlive |
+            PacketType::PlayServerboundClickContainer |
+            PacketType::PlayServerboundCloseContainer |
+            PacketType::PlayServerboundPlayerAction |
+            PacketType::PlayServerboundUseItem |
+            PacketType::PlayServerboundPong |
+            PacketType::PlayServerboundPluginMessage => Direction::Serverbound,
+
+            PacketType::StatusClientboundResponse |
+            PacketType::StatusClientboundPong |
+            PacketType::LoginClientboundEncryptionRequest |
+            PacketType::LoginClientboundSuccess |
+            PacketType::LoginClientboundDisconnect |
+            PacketType::LoginClientboundSetCompression |
+            PacketType::PlayClientboundLogin |
+            PacketType::PlayClientboundDifficulty |
+            PacketType::PlayClientboundAbilities |
+            PacketType::PlayClientboundSetDefaultSpawnPosition |
+            PacketType::PlayClientboundKeepAlive |
+            PacketType::PlayClientboundDisconnect |
+            PacketType::PlayClientboundPlayerInfoUpdate |
+            PacketType::PlayClientboundChunkDataAndLight |
+            PacketType::PlayClientboundSetCenterChunk |
+            PacketType::PlayClientboundSynchronizePlayerPosition |
+            PacketType::PlayClientboundSystemChatMessage |
+            PacketType::PlayClientboundPluginMessage |
+            PacketType::PlayClientboundPing => Direction::Clientbound,
+        }
+    }
+
+    // the connection state the packet is sent in
+    pub fn state(&self) -> ConnectionState {
+        match self {
+            PacketType::HandshakeServerboundStart => ConnectionState::Handshake,
+
+            PacketType::StatusServerboundRequest |
+            PacketType::StatusClientboundResponse |
+            PacketType::StatusServerboundPing |
+            PacketType::StatusClientboundPong => ConnectionState::Status,
+
+            PacketType::LoginServerboundStart |
+            PacketType::LoginClientboundEncryptionRequest |
+            PacketType::LoginServerboundEncryptionResponse |
+            PacketType::LoginClientboundSuccess |
+            PacketType::LoginClientboundDisconnect |
+            PacketType::LoginClientboundSetCompression => ConnectionState::Login,
+
+            _ => ConnectionState::Play,
+        }
+    }
 }
 
 #[derive(Hash, PartialEq, Eq)]
@@ -32,34 +150,123 @@ struct PacketTypeKey {
     id: i32,
 }
 
-lazy_static! {
-    static ref SERVERBOUND_PACKET_TYPES: HashMap<PacketTypeKey, PacketType> = HashMap::from([
+// the packet ids of one protocol version
+pub(crate) struct PacketIds {
+    serverbound: HashMap<PacketTypeKey, PacketType>,
+    clientbound: HashMap<PacketType, i32>,
+    serverbound_ids: HashMap<PacketType, i32>,
+    // for reading what a backend sends in proxy mode
+    clientbound_types: HashMap<PacketTypeKey, PacketType>,
+}
+
+impl PacketIds {
+    fn create(serverbound: HashMap<PacketTypeKey, PacketType>, clientbound: HashMap<PacketType, i32>) -> PacketIds {
+        let serverbound_ids = serverbound.iter()
+            .map(|(key, packet_type)| (*packet_type, key.id))
+            .collect();
+
+        let clientbound_types = clientbound.iter()
+            .map(|(packet_type, id)| (PacketTypeKey { state: packet_type.state(), id: *id }, *packet_type))
+            .collect();
+
+        PacketIds { serverbound, clientbound, serverbound_ids, clientbound_types }
+    }
+
+    // the supported versions are the ones with a table of their own
+    pub(crate) fn exist_for(protocol: ProtocolVersion) -> bool {
+        PACKET_IDS.contains_key(&protocol)
+    }
+
+    // versions without a table of their own, only accepted with experimental_protocols, use the
+    // ids of the latest supported one
+    fn of(protocol: ProtocolVersion) -> &'static PacketIds {
+        PACKET_IDS.get(&protocol).unwrap_or_else(|| &PACKET_IDS[&ProtocolVersion::LATEST_SUPPORTED])
+    }
+}
+
+fn serverbound_packet_types_1_19_4() -> HashMap<PacketTypeKey, PacketType> {
+    HashMap::from([
         (PacketTypeKey { state: ConnectionState::Handshake, id: 0x00 }, PacketType::HandshakeServerboundStart),
         (PacketTypeKey { state: ConnectionState::Status, id: 0x00 }, PacketType::StatusServerboundRequest),
         (PacketTypeKey { state: ConnectionState::Status, id: 0x01 }, PacketType::StatusServerboundPing),
         (PacketTypeKey { state: ConnectionState::Login, id: 0x00 }, PacketType::LoginServerboundStart),
-    ]);
+        (PacketTypeKey { state: ConnectionState::Login, id: 0x01 }, PacketType::LoginServerboundEncryptionResponse),
+        (PacketTypeKey { state: ConnectionState::Play, id: 0x00 }, PacketType::PlayServerboundConfirmTeleportation),
+        (PacketTypeKey { state: ConnectionState::Play, id: 0x03 }, PacketType::PlayServerboundMessageAcknowledgment),
+        (PacketTypeKey { state: ConnectionState::Play, id: 0x04 }, PacketType::PlayServerboundChatCommand),
+        (PacketTypeKey { state: ConnectionState::Play, id: 0x05 }, PacketType::PlayServerboundChatMessage),
+        (PacketTypeKey { state: ConnectionState::Play, id: 0x08 }, PacketType::PlayServerboundClientInformation),
+        (PacketTypeKey { state: ConnectionState::Play, id: 0x0B }, PacketType::PlayServerboundClickContainer),
+        (PacketTypeKey { state: ConnectionState::Play, id: 0x0C }, PacketType::PlayServerboundCloseContainer),
+        (PacketTypeKey { state: ConnectionState::Play, id: 0x0D }, PacketType::PlayServerboundPluginMessage),
+        (PacketTypeKey { state: ConnectionState::Play, id: 0x12 }, PacketType::PlayServerboundKeepAlive),
+        (PacketTypeKey { state: ConnectionState::Play, id: 0x14 }, PacketType::PlayServerboundSetPlayerPosition),
+        (PacketTypeKey { state: ConnectionState::Play, id: 0x15 }, PacketType::PlayServerboundSetPlayerPositionAndRotation),
+        (PacketTypeKey { state: ConnectionState::Play, id: 0x16 }, PacketType::PlayServerboundSetPlayerRotation),
+        (PacketTypeKey { state: ConnectionState::Play, id: 0x1D }, PacketType::PlayServerboundPlayerAction),
+        (PacketTypeKey { state: ConnectionState::Play, id: 0x20 }, PacketType::PlayServerboundPong),
+        (PacketTypeKey { state: ConnectionState::Play, id: 0x32 }, PacketType::PlayServerboundUseItem),
+    ])
+}
 
-    static ref CLIENTBOUND_PACKET_TYPES: HashMap<PacketType, i32> = HashMap::from([
+fn clientbound_packet_ids_1_19_4() -> HashMap<PacketType, i32> {
+    HashMap::from([
         (PacketType::StatusClientboundResponse, 0x00),
         (PacketType::StatusClientboundPong, 0x01),
+        (PacketType::LoginClientboundDisconnect, 0x00),
+        (PacketType::LoginClientboundEncryptionRequest, 0x01),
         (PacketType::LoginClientboundSuccess, 0x02),
+        (PacketType::LoginClientboundSetCompression, 0x03),
         (PacketType::PlayClientboundLogin, 0x28),
         (PacketType::PlayClientboundDifficulty, 0x0C),
         (PacketType::PlayClientboundAbilities, 0x34),
-        (PacketType::PlayClientboundSetDefaultSpawnPosition, 0x50)
+        (PacketType::PlayClientboundSetDefaultSpawnPosition, 0x50),
+        (PacketType::PlayClientboundKeepAlive, 0x23),
+        (PacketType::PlayClientboundPluginMessage, 0x17),
+        (PacketType::PlayClientboundDisconnect, 0x1A),
+        (PacketType::PlayClientboundPlayerInfoUpdate, 0x3A),
+        (PacketType::PlayClientboundChunkDataAndLight, 0x24),
+        (PacketType::PlayClientboundSetCenterChunk, 0x4E),
+        (PacketType::PlayClientboundSynchronizePlayerPosition, 0x3C),
+        (PacketType::PlayClientboundSystemChatMessage, 0x64),
+        (PacketType::PlayClientboundPing, 0x32)
+    ])
+}
+
+// 1.20 changed the fields of some packets but none of the ids, so its tables are those of 1.19.4
+fn serverbound_packet_types_1_20() -> HashMap<PacketTypeKey, PacketType> {
+    serverbound_packet_types_1_19_4()
+}
+
+fn clientbound_packet_ids_1_20() -> HashMap<PacketType, i32> {
+    clientbound_packet_ids_1_19_4()
+}
+
+lazy_static! {
+    static ref PACKET_IDS: HashMap<ProtocolVersion, PacketIds> = HashMap::from([
+        (ProtocolVersion::V1_19_4, PacketIds::create(serverbound_packet_types_1_19_4(), clientbound_packet_ids_1_19_4())),
+        (ProtocolVersion::V1_20, PacketIds::create(serverbound_packet_types_1_20(), clientbound_packet_ids_1_20())),
     ]);
 }
 
 #[derive(Debug)]
 pub enum DecodingError {
     PacketTooSmall,
+    PacketTooLarge(i32),
+    // a complete frame that's too short for what it has to hold
+    InvalidPacket,
     VarIntTooBig,
+    VarLongTooBig,
     InvalidPacketId(i32, ConnectionState),
     StringTooSmall,
     StringTooLarge,
     StringInvalidUtf8(Utf8Error),
     InvalidClientboundPacket(PacketType),
+    ArrayTooLarge,
+    TooDeeplyNested,
+    TrailingBytes(usize),
+    InvalidCompressedData,
+    UnknownMetadataType(i32),
 }
 
 impl Display for DecodingError {
@@ -70,6 +277,73 @@ impl Display for DecodingError {
 
 impl Error for DecodingError {}
 
+#[derive(Debug)]
+pub enum EncodingError {
+    UnknownPacketType(PacketType),
+    PositionOutOfRange(i32, i32, i32),
+    InvalidAngle(f32),
+    StringTooLarge(usize, usize),
+}
+
+impl Display for EncodingError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        (self as &dyn Debug).fmt(f)
+    }
+}
+
+impl Error for EncodingError {}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct SpawnPosition {
+    x: i32,
+    y: i16,
+    z: i32,
+    angle: f32,
+}
+
+impl SpawnPosition {
+    const HORIZONTAL_RANGE: std::ops::RangeInclusive<i32> = -(1 << 25)..=(1 << 25) - 1;
+    const VERTICAL_RANGE: std::ops::RangeInclusive<i32> = -(1 << 11)..=(1 << 11) - 1;
+
+    pub fn new(x: i32, y: i32, z: i32, angle: f32) -> Result<Self, EncodingError> {
+        if !Self::HORIZONTAL_RANGE.contains(&x) || !Self::VERTICAL_RANGE.contains(&y) || !Self::HORIZONTAL_RANGE.contains(&z) {
+            return Err(EncodingError::PositionOutOfRange(x, y, z));
+        }
+
+        if !angle.is_finite() {
+            return Err(EncodingError::InvalidAngle(angle));
+        }
+
+        Ok(SpawnPosition {
+            x,
+            y: y as i16,
+            z,
+            angle: angle.rem_euclid(360.0),
+        })
+    }
+
+    pub fn x(&self) -> i32 { self.x }
+
+    pub fn y(&self) -> i16 { self.y }
+
+    pub fn z(&self) -> i32 { self.z }
+
+    pub fn angle(&self) -> f32 { self.angle }
+}
+
+pub const MAX_KNOWN_PACKS: usize = 64;
+
+// limits of the signed arguments in a chat command
+pub const MAX_COMMAND_ARGUMENT_SIGNATURES: usize = 8;
+pub const MESSAGE_SIGNATURE_LENGTH: usize = 256;
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct KnownPack {
+    pub namespace: String,
+    pub id: String,
+    pub version: String,
+}
+
 pub struct Packet {
     pub data: Vec<u8>,
     pub raw_size: usize,
@@ -77,34 +351,104 @@ pub struct Packet {
 }
 
 impl Packet {
-    pub async fn decode(buf: &Vec<u8>, state: ConnectionState) -> Result<Packet, DecodingError> {
+    pub async fn decode(buf: &[u8], state: ConnectionState, protocol: ProtocolVersion, compression_threshold: Option<usize>) -> Result<Packet, DecodingError> {
         let mut reader = PacketReader::create(buf);
 
-        Self::read(&mut reader, state)
+        Self::read(&mut reader, Direction::Serverbound, state, PacketIds::of(protocol), compression_threshold)
     }
 
-    fn read(reader: &mut PacketReader, state: ConnectionState) -> Result<Packet, DecodingError> {
-        let packet_beginning = reader.reader_index;
+    // like decode, for the packets a server sends
+    pub async fn decode_clientbound(buf: &[u8], state: ConnectionState, protocol: ProtocolVersion, compression_threshold: Option<usize>) -> Result<Packet, DecodingError> {
+        let mut reader = PacketReader::create(buf);
+
+        Self::read(&mut reader, Direction::Clientbound, state, PacketIds::of(protocol), compression_threshold)
+    }
+
+    pub fn frame_size(buf: &[u8]) -> Result<usize, DecodingError> {
+        let mut reader = PacketReader::create(buf);
+        let (length, length_size) = reader.read_varint_with_size()?;
 
-        if reader.left_to_read() < 1 {
+        if length < 0 || length as usize > reader.left_to_read() {
             return Err(DecodingError::PacketTooSmall);
         }
 
-        let length = reader.read_varint()?;
+        Ok(length_size + length as usize)
+    }
+
+    pub fn encode(&self, protocol: ProtocolVersion) -> Result<Vec<u8>, EncodingError> {
+        self.encode_compressed(protocol, None)
+    }
+
+    // like encode, in the compressed format once a compression threshold was set
+    pub fn encode_compressed(&self, protocol: ProtocolVersion, compression_threshold: Option<usize>) -> Result<Vec<u8>, EncodingError> {
+        self.encode_with_ids(PacketIds::of(protocol), compression_threshold)
+    }
+
+    fn encode_with_ids(&self, ids: &PacketIds, compression_threshold: Option<usize>) -> Result<Vec<u8>, EncodingError> {
+        let ids = match self.packet_type.direction() {
+            Direction::Clientbound => &ids.clientbound,
+            Direction::Serverbound => &ids.serverbound_ids,
+        };
+
+        let packet_id = match ids.get(&self.packet_type) {
+            Some(id) => *id,
+            None => return Err(EncodingError::UnknownPacketType(self.packet_type))
+        };
+
+        let mut body = PacketWriter::create(self.data.len() + 5);
+        body.write_var_int(packet_id);
+        body.write_all(&self.data).unwrap();
+
+        // writing to memory can't fail
+        let mut frame = PacketWriter::create(body.len() + 10);
+        body.write_frame(&mut frame, compression_threshold).unwrap();
+
+        Ok(frame.buf)
+    }
+
+    fn read(reader: &mut PacketReader, direction: Direction, state: ConnectionState, ids: &PacketIds, compression_threshold: Option<usize>) -> Result<Packet, DecodingError> {
+        let packet_beginning = reader.reader_index;
+
+        let (length, length_size) = match reader.peek_varint()? {
+            Some(length) => length,
+            None => return Err(DecodingError::PacketTooSmall)
+        };
+        reader.reader_index += length_size;
+
+        // negative lengths are varints that overflowed into the sign bit
+        if !(0..=MAX_PACKET_LENGTH).contains(&length) {
+            return Err(DecodingError::PacketTooLarge(length));
+        }
+
+        // every packet starts with its id, so it can't be empty
+        if length == 0 {
+            return Err(DecodingError::InvalidPacket);
+        }
 
         if length > reader.left_to_read() as i32 {
             return Err(DecodingError::PacketTooSmall);
         }
 
-        let (packet_id, packet_id_size) = reader.read_varint_with_size()?;
-        let packet_type = Self::packet_id_to_type(packet_id, state)?;
+        let mut body: Vec<u8> = vec![0; length as usize];
+        reader.try_read_all(&mut body).expect("this should not happen");
+
+        // all of the frame arrived, so running out of bytes within it means it's malformed rather
+        // than that more have to be waited for
+        let invalid_if_too_small = |e| match e {
+            DecodingError::PacketTooSmall => DecodingError::InvalidPacket,
+            e => e
+        };
+
+        if let Some(threshold) = compression_threshold {
+            body = Self::decompress(&body, threshold).map_err(invalid_if_too_small)?;
+        }
 
-        let buffer_length = (length as usize) - packet_id_size;
-        let mut buffer: Vec<u8> = vec![0; buffer_length];
-        reader.try_read_all(&mut buffer).expect("this should not happen");
+        let mut body_reader = PacketReader::create(&body);
+        let packet_id = body_reader.read_varint().map_err(invalid_if_too_small)?;
+        let packet_type = Self::packet_id_to_type(packet_id, direction, state, ids)?;
 
         let packet = Packet {
-            data: buffer,
+            data: body[body_reader.reader_index..].to_vec(),
             raw_size: reader.reader_index - packet_beginning,
             packet_type,
         };
@@ -112,15 +456,48 @@ impl Packet {
         Ok(packet)
     }
 
-    fn packet_id_to_type(id: i32, state: ConnectionState) -> Result<PacketType, DecodingError> {
-        match SERVERBOUND_PACKET_TYPES.get(&PacketTypeKey { state, id }) {
+    // unwraps the body of a frame in the compressed format: the uncompressed length, or 0 when
+    // the rest of the frame isn't compressed, followed by the (zlib deflated) packet id and data
+    fn decompress(frame: &[u8], threshold: usize) -> Result<Vec<u8>, DecodingError> {
+        let mut reader = PacketReader::create(frame);
+        let data_length = reader.read_varint()?;
+        let compressed = &frame[reader.reader_index..];
+
+        if data_length == 0 {
+            return Ok(compressed.to_vec());
+        }
+
+        if data_length < threshold as i32 || data_length > MAX_UNCOMPRESSED_LENGTH {
+            return Err(DecodingError::InvalidCompressedData);
+        }
+
+        let mut data = Vec::with_capacity(data_length as usize);
+        ZlibDecoder::new(compressed)
+            .take(data_length as u64 + 1)
+            .read_to_end(&mut data)
+            .map_err(|_| DecodingError::InvalidCompressedData)?;
+
+        if data.len() != data_length as usize {
+            return Err(DecodingError::InvalidCompressedData);
+        }
+
+        Ok(data)
+    }
+
+    fn packet_id_to_type(id: i32, direction: Direction, state: ConnectionState, ids: &PacketIds) -> Result<PacketType, DecodingError> {
+        let types = match direction {
+            Direction::Serverbound => &ids.serverbound,
+            Direction::Clientbound => &ids.clientbound_types,
+        };
+
+        match types.get(&PacketTypeKey { state, id }) {
             Some(packet_type) => Ok(*packet_type),
             None => Err(DecodingError::InvalidPacketId(id, state))
         }
     }
 
-    fn packet_type_to_id(packet_type: PacketType) -> Result<i32, DecodingError> {
-        match CLIENTBOUND_PACKET_TYPES.get(&packet_type) {
+    fn packet_type_to_id(packet_type: PacketType, protocol: ProtocolVersion) -> Result<i32, DecodingError> {
+        match PacketIds::of(protocol).clientbound.get(&packet_type) {
             Some(packet_type) => Ok(*packet_type),
             None => Err(DecodingError::InvalidClientboundPacket(packet_type))
         }
@@ -128,21 +505,49 @@ impl Packet {
 }
 
 
+pub const DEFAULT_MAX_NESTING_DEPTH: usize = 16;
+
 pub struct PacketReader<'a> {
-    buf: &'a Vec<u8>,
+    buf: &'a [u8],
     reader_index: usize,
+    depth: usize,
+    max_depth: usize,
 }
 
 impl<'a> PacketReader<'a> {
-    pub fn create(buf: &'a Vec<u8>) -> Self {
+    pub fn create(buf: &'a [u8]) -> Self {
         PacketReader {
             buf,
             reader_index: 0,
+            depth: 0,
+            max_depth: DEFAULT_MAX_NESTING_DEPTH,
+        }
+    }
+
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    // runs a read that may recurse into further nested reads, bounded by max_depth
+    pub fn nested<T, F>(&mut self, read: F) -> Result<T, DecodingError>
+        where F: FnOnce(&mut Self) -> Result<T, DecodingError> {
+        if self.depth >= self.max_depth {
+            return Err(DecodingError::TooDeeplyNested);
         }
+
+        self.depth += 1;
+        let result = read(self);
+        self.depth -= 1;
+
+        result
     }
 
     pub fn left_to_read(&self) -> usize { self.buf.len() - self.reader_index }
 
+    pub fn skip_remaining(&mut self) {
+        self.reader_index = self.buf.len();
+    }
+
     pub fn ensure_at_least(&self, len: usize) -> Result<(), DecodingError> {
         if self.reader_index + len > self.buf.len() {
             Err(DecodingError::StringTooSmall)
@@ -165,7 +570,7 @@ impl<'a> PacketReader<'a> {
         }
     }
 
-    pub fn try_read_all(&mut self, out: &mut Vec<u8>) -> Result<(), DecodingError> {
+    pub fn try_read_all(&mut self, out: &mut [u8]) -> Result<(), DecodingError> {
         let len = out.len();
         self.ensure_at_least(len)?;
 
@@ -175,12 +580,33 @@ impl<'a> PacketReader<'a> {
         Ok(())
     }
 
+    #[inline]
     pub fn read_varint(&mut self) -> Result<i32, DecodingError> {
+        // most varints on the wire fit in a single byte
+        if let Some(&first_byte) = self.buf.get(self.reader_index) {
+            if (first_byte & 0x80) == 0 {
+                self.reader_index += 1;
+                return Ok(first_byte as i32);
+            }
+        }
+
+        self.read_varint_bytewise()
+    }
+
+    // read_varint without the shortcut for single byte varints, public so the benchmark can
+    // compare the two
+    pub fn read_varint_bytewise(&mut self) -> Result<i32, DecodingError> {
         let mut value: i32 = 0;
         let mut position: i32 = 0;
 
         loop {
             let current_byte = self.try_read_one()? as i32;
+
+            // the fifth byte only holds the top four bits, anything above doesn't fit in an i32
+            if position == 28 && (current_byte & 0x70) != 0 {
+                return Err(DecodingError::VarIntTooBig);
+            }
+
             value |= (current_byte & 0x7F) << position;
 
             if (current_byte & 0x80) == 0 {
@@ -197,6 +623,48 @@ impl<'a> PacketReader<'a> {
         Ok(value)
     }
 
+    pub fn read_var_long(&mut self) -> Result<i64, DecodingError> {
+        let mut value: i64 = 0;
+        let mut position: i64 = 0;
+
+        loop {
+            let current_byte = self.try_read_one()? as i64;
+
+            // the tenth byte only holds the sign bit
+            if position == 63 && (current_byte & 0x7E) != 0 {
+                return Err(DecodingError::VarLongTooBig);
+            }
+
+            value |= (current_byte & 0x7F) << position;
+
+            if (current_byte & 0x80) == 0 {
+                break;
+            }
+
+            position += 7;
+
+            if position >= 70 {
+                return Err(DecodingError::VarLongTooBig);
+            }
+        }
+
+        Ok(value)
+    }
+
+    // reads the next varint and its size without consuming it, None while it's cut off by the
+    // end of the buffer
+    pub fn peek_varint(&mut self) -> Result<Option<(i32, usize)>, DecodingError> {
+        let index_before = self.reader_index;
+        let result = self.read_varint_with_size();
+        self.reader_index = index_before;
+
+        match result {
+            Ok(varint) => Ok(Some(varint)),
+            Err(DecodingError::PacketTooSmall) => Ok(None),
+            Err(e) => Err(e)
+        }
+    }
+
     pub fn read_varint_with_size(&mut self) -> Result<(i32, usize), DecodingError> {
         let index_before = self.reader_index;
         let varint = self.read_varint()?;
@@ -205,8 +673,10 @@ impl<'a> PacketReader<'a> {
     }
 
     pub fn read_string(&mut self, max_length: usize) -> Result<String, DecodingError> {
+        // max_length is in UTF-16 code units like in write_string_checked, which take up to 3 bytes
+        // each on the wire
         let size = self.read_varint()? as usize;
-        if size > max_length {
+        if size > max_length * 3 {
             return Err(DecodingError::StringTooLarge);
         }
 
@@ -215,10 +685,35 @@ impl<'a> PacketReader<'a> {
         let slice = &self.buf[self.reader_index..self.reader_index + size];
         self.reader_index += size;
 
-        match std::str::from_utf8(slice) {
-            Ok(str) => Ok(str.to_string()),
-            Err(e) => Err(DecodingError::StringInvalidUtf8(e))
+        let str = std::str::from_utf8(slice).map_err(DecodingError::StringInvalidUtf8)?;
+        if str.encode_utf16().count() > max_length {
+            return Err(DecodingError::StringTooLarge);
         }
+
+        Ok(str.to_string())
+    }
+
+    pub fn read_byte_array(&mut self, max_length: usize) -> Result<Vec<u8>, DecodingError> {
+        let length = self.read_varint()?;
+        if length < 0 || length as usize > max_length {
+            return Err(DecodingError::ArrayTooLarge);
+        }
+
+        // check before allocating, the length could be anything
+        self.ensure_at_least(length as usize)?;
+
+        let mut bytes = vec![0u8; length as usize];
+        self.try_read_all(&mut bytes)?;
+
+        Ok(bytes)
+    }
+
+    // for packets whose last field takes up the rest of the packet
+    pub fn read_remaining(&mut self) -> &'a [u8] {
+        let remaining = &self.buf[self.reader_index..];
+        self.reader_index = self.buf.len();
+
+        remaining
     }
 
     pub fn read_boolean(&mut self) -> Result<bool, DecodingError> {
@@ -235,7 +730,7 @@ impl<'a> PacketReader<'a> {
     }
 
     pub fn read_long(&mut self) -> Result<i64, DecodingError> {
-        self.ensure_at_least(2)?;
+        self.ensure_at_least(8)?;
 
         let result = ((self.read_one_unsafe() as i64) << 56) |
             ((self.read_one_unsafe() as i64) << 48) |
@@ -250,6 +745,47 @@ impl<'a> PacketReader<'a> {
         Ok(result)
     }
 
+    pub fn read_byte(&mut self) -> Result<i8, DecodingError> {
+        Ok(self.try_read_one()? as i8)
+    }
+
+    pub fn read_ubyte(&mut self) -> Result<u8, DecodingError> {
+        self.try_read_one()
+    }
+
+    pub fn read_int(&mut self) -> Result<i32, DecodingError> {
+        let mut bytes = [0u8; 4];
+        self.try_read_all(&mut bytes)?;
+
+        Ok(i32::from_be_bytes(bytes))
+    }
+
+    pub fn read_float(&mut self) -> Result<f32, DecodingError> {
+        let mut bytes = [0u8; 4];
+        self.try_read_all(&mut bytes)?;
+
+        Ok(f32::from_be_bytes(bytes))
+    }
+
+    pub fn read_double(&mut self) -> Result<f64, DecodingError> {
+        let mut bytes = [0u8; 8];
+        self.try_read_all(&mut bytes)?;
+
+        Ok(f64::from_be_bytes(bytes))
+    }
+
+    // three doubles, as used for entity and player positions
+    pub fn read_vec3d(&mut self) -> Result<(f64, f64, f64), DecodingError> {
+        Ok((self.read_double()?, self.read_double()?, self.read_double()?))
+    }
+
+    // unpacks the 26 bit x, 12 bit y and 26 bit z of a block position
+    pub fn read_position(&mut self) -> Result<(i32, i16, i32), DecodingError> {
+        let value = self.read_long()?;
+
+        Ok(((value >> 38) as i32, ((value << 52) >> 52) as i16, ((value << 26) >> 38) as i32))
+    }
+
     pub fn read_uuid(&mut self) -> Result<Uuid, DecodingError> {
         Ok(Uuid::from_u64_pair(
             self.read_long()? as u64,
@@ -257,10 +793,28 @@ impl<'a> PacketReader<'a> {
         ))
     }
 
+    pub fn read_known_packs(&mut self) -> Result<Vec<KnownPack>, DecodingError> {
+        let count = self.read_varint()? as usize;
+        if count > MAX_KNOWN_PACKS {
+            return Err(DecodingError::ArrayTooLarge);
+        }
+
+        let mut packs = Vec::with_capacity(count);
+        for _ in 0..count {
+            packs.push(KnownPack {
+                namespace: self.read_string(32767)?,
+                id: self.read_string(32767)?,
+                version: self.read_string(32767)?,
+            });
+        }
+
+        Ok(packs)
+    }
+
     pub fn read_optional<T, F>(&mut self, read: F) -> Result<Option<T>, DecodingError>
         where F: FnOnce(&mut Self) -> Result<T, DecodingError> {
         if self.read_boolean()? {
-            let result = read(self)?;
+            let result = self.nested(read)?;
 
             Ok(Some(result))
         } else {
@@ -280,61 +834,89 @@ impl PacketWriter {
         }
     }
 
-    pub fn write_packet_type(&mut self, packet_type: PacketType) {
-        self.write_var_int(Packet::packet_type_to_id(packet_type).expect("sending invalid packet"));
+    pub fn write_packet_type(&mut self, packet_type: PacketType, protocol: ProtocolVersion) {
+        self.write_var_int(Packet::packet_type_to_id(packet_type, protocol).expect("sending invalid packet"));
     }
 
-    pub fn write_byte(&mut self, byte: u8) {
+    pub fn write_byte(&mut self, value: i8) {
+        self.buf.push(value as u8)
+    }
+
+    pub fn write_ubyte(&mut self, byte: u8) {
         self.buf.push(byte)
     }
 
     pub fn write_boolean(&mut self, boolean: bool) {
-        self.write_byte(if boolean { 1 } else { 0 });
+        self.write_ubyte(if boolean { 1 } else { 0 });
+    }
+
+    pub fn write_short(&mut self, value: u16) {
+        self.buf.extend_from_slice(&value.to_be_bytes());
     }
 
     pub fn write_int(&mut self, value: i32) {
         self.buf.reserve(4);
 
-        self.write_byte(((value >> 24) & 0xFF) as u8);
-        self.write_byte(((value >> 16) & 0xFF) as u8);
-        self.write_byte(((value >> 8) & 0xFF) as u8);
-        self.write_byte((value  & 0xFF) as u8);
+        self.write_ubyte(((value >> 24) & 0xFF) as u8);
+        self.write_ubyte(((value >> 16) & 0xFF) as u8);
+        self.write_ubyte(((value >> 8) & 0xFF) as u8);
+        self.write_ubyte((value  & 0xFF) as u8);
     }
 
     pub fn write_long(&mut self, value: i64) {
         self.buf.reserve(8);
 
-        self.write_byte(((value >> 56) & 0xFF) as u8);
-        self.write_byte(((value >> 48) & 0xFF) as u8);
-        self.write_byte(((value >> 40) & 0xFF) as u8);
-        self.write_byte(((value >> 32) & 0xFF) as u8);
+        self.write_ubyte(((value >> 56) & 0xFF) as u8);
+        self.write_ubyte(((value >> 48) & 0xFF) as u8);
+        self.write_ubyte(((value >> 40) & 0xFF) as u8);
+        self.write_ubyte(((value >> 32) & 0xFF) as u8);
 
-        self.write_byte(((value >> 24) & 0xFF) as u8);
-        self.write_byte(((value >> 16) & 0xFF) as u8);
-        self.write_byte(((value >> 8) & 0xFF) as u8);
-        self.write_byte((value & 0xFF) as u8);
+        self.write_ubyte(((value >> 24) & 0xFF) as u8);
+        self.write_ubyte(((value >> 16) & 0xFF) as u8);
+        self.write_ubyte(((value >> 8) & 0xFF) as u8);
+        self.write_ubyte((value & 0xFF) as u8);
     }
 
     pub fn write_float(&mut self, value: f32) {
         self.write_all(value.to_be_bytes().as_ref()).unwrap();
     }
 
+    pub fn write_double(&mut self, value: f64) {
+        self.write_all(value.to_be_bytes().as_ref()).unwrap();
+    }
+
+    pub fn write_vec3d(&mut self, x: f64, y: f64, z: f64) {
+        self.write_double(x);
+        self.write_double(y);
+        self.write_double(z);
+    }
+
     pub fn write_position(&mut self, x: i32, y: i16, z: i32) {
         self.write_long(((x as i64 & 0x3FFFFFFi64) << 38) | ((z as i64 & 0x3FFFFFF) << 12) | (y as i64 & 0xFFF))
     }
 
+    pub fn write_spawn_position(&mut self, position: &SpawnPosition) {
+        self.write_position(position.x, position.y, position.z);
+        self.write_float(position.angle);
+    }
+
     pub fn write_var_int(&mut self, value: i32) {
+        let (bytes, len) = encode_var_int(value);
+        self.buf.extend_from_slice(&bytes[..len]);
+    }
+
+    pub fn write_var_long(&mut self, value: i64) {
         let mut current_value = value;
 
         loop {
-            if (current_value & 0x7F.not()) == 0 {
-                self.write_byte(current_value as u8);
+            if (current_value & 0x7Fi64.not()) == 0 {
+                self.write_ubyte(current_value as u8);
                 break;
             }
 
-            self.write_byte(((current_value & 0x7F) | 0x80) as u8);
+            self.write_ubyte(((current_value & 0x7F) | 0x80) as u8);
 
-            current_value = (((current_value) as u32) >> 7) as i32;
+            current_value = (((current_value) as u64) >> 7) as i64;
         }
     }
 
@@ -343,16 +925,90 @@ impl PacketWriter {
         self.write_all(str.as_bytes()).unwrap();
     }
 
+    // like write_string, but refuses strings the client would reject. the length prefix counts
+    // bytes while max_length counts utf-16 code units, which is how minecraft defines its limits
+    pub fn write_string_checked(&mut self, str: &str, max_length: usize) -> Result<(), EncodingError> {
+        let length = str.encode_utf16().count();
+        if length > max_length {
+            return Err(EncodingError::StringTooLarge(length, max_length));
+        }
+
+        self.write_string(str);
+        Ok(())
+    }
+
+    pub fn write_byte_array(&mut self, bytes: &[u8]) {
+        self.write_var_int(bytes.len() as i32);
+        self.write_all(bytes).unwrap();
+    }
+
     pub fn write_uuid(&mut self, uuid: Uuid) {
         let (msb, lsb) = uuid.as_u64_pair();
         self.write_long(msb as i64);
         self.write_long(lsb as i64);
     }
 
+    pub fn write_known_packs(&mut self, packs: &[KnownPack]) {
+        self.write_var_int(packs.len() as i32);
+
+        for pack in packs {
+            self.write_string(&pack.namespace);
+            self.write_string(&pack.id);
+            self.write_string(&pack.version);
+        }
+    }
+
+    // writes the length and the packet with a single write, so they are never torn apart
+    pub async fn flush_to(&self, stream: &mut (impl AsyncWrite + Unpin)) -> std::io::Result<()> {
+        let mut frame = PacketWriter::create(self.len() + 5);
+        self.write_frame(&mut frame, None)?;
+
+        stream.write_all(frame.as_ref()).await
+    }
+
+    // writes the frame in the compressed format, deflating packets larger than the threshold
+    pub async fn flush_to_compressed(&self, stream: &mut (impl AsyncWrite + Unpin), threshold: usize) -> std::io::Result<()> {
+        let mut frame = PacketWriter::create(self.len() + 10);
+        self.write_frame(&mut frame, Some(threshold))?;
+
+        stream.write_all(frame.as_ref()).await
+    }
+
+    // appends the length-prefixed frame of this packet to out
+    pub fn write_frame(&self, out: &mut PacketWriter, compression_threshold: Option<usize>) -> std::io::Result<()> {
+        let threshold = match compression_threshold {
+            Some(threshold) => threshold,
+            None => {
+                out.write_var_int(self.len() as i32);
+                return out.write_all(self.as_ref());
+            }
+        };
+
+        let mut body = PacketWriter::create(self.len() + 5);
+
+        if self.len() > threshold {
+            body.write_var_int(self.len() as i32);
+
+            let mut encoder = ZlibEncoder::new(body, Compression::default());
+            encoder.write_all(self.as_ref())?;
+            body = encoder.finish()?;
+        } else {
+            body.write_var_int(0);
+            body.write_all(self.as_ref())?;
+        }
+
+        out.write_var_int(body.len() as i32);
+        out.write_all(body.as_ref())
+    }
+
     pub fn len(&self) -> usize {
         self.buf.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
     pub fn reset(&mut self) {
         self.buf.clear();
     }
@@ -377,19 +1033,653 @@ impl AsRef<[u8]> for PacketWriter {
     }
 }
 
-pub async fn write_var_int(target: &mut (impl AsyncWrite + Unpin), value: i32) -> std::io::Result<()> {
+impl AsMut<[u8]> for PacketWriter {
+    fn as_mut(&mut self) -> &mut [u8] {
+        self.buf.as_mut()
+    }
+}
+
+// 16 bytes per row as offset, hex and the printable ascii characters, for debugging packets
+pub fn hex_dump(bytes: &[u8]) -> String {
+    let mut dump = String::with_capacity(bytes.len().div_ceil(16) * 78);
+
+    for (row, chunk) in bytes.chunks(16).enumerate() {
+        if row > 0 {
+            dump.push('\n');
+        }
+
+        dump.push_str(&format!("{:08x} ", row * 16));
+
+        for column in 0..16 {
+            if column == 8 {
+                dump.push(' ');
+            }
+
+            match chunk.get(column) {
+                Some(byte) => dump.push_str(&format!(" {:02x}", byte)),
+                None => dump.push_str("   ")
+            }
+        }
+
+        dump.push_str("  |");
+        dump.extend(chunk.iter().map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' }));
+        dump.push('|');
+    }
+
+    dump
+}
+
+// the encoding shared by every varint writer, in a buffer of the largest size and its used length
+pub fn encode_var_int(value: i32) -> ([u8; 5], usize) {
+    let mut bytes = [0u8; 5];
+    let mut len = 0;
     let mut current_value = value;
 
     loop {
         if (current_value & 0x7F.not()) == 0 {
-            target.write_all(&[current_value as u8]).await?;
-            break;
+            bytes[len] = current_value as u8;
+            return (bytes, len + 1);
         }
 
-        target.write_all(&[((current_value & 0x7F) | 0x80) as u8]).await?;
+        bytes[len] = ((current_value & 0x7F) | 0x80) as u8;
+        len += 1;
 
         current_value = (((current_value) as u32) >> 7) as i32;
     }
+}
+
+pub async fn write_var_int(target: &mut (impl AsyncWrite + Unpin), value: i32) -> std::io::Result<()> {
+    let (bytes, len) = encode_var_int(value);
+    target.write_all(&bytes[..len]).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a frame as a client would send it, length, id and data
+    fn frame(id: i32, data: &[u8]) -> Vec<u8> {
+        let mut body = PacketWriter::create(data.len() + 5);
+        body.write_var_int(id);
+        body.write_all(data).unwrap();
+
+        let mut frame = PacketWriter::create(body.len() + 5);
+        frame.write_var_int(body.len() as i32);
+        frame.write_all(body.as_ref()).unwrap();
+        frame.buf
+    }
+
+    #[tokio::test]
+    async fn encoding_a_decoded_packet_reproduces_its_bytes() {
+        let mut handshake = PacketWriter::create(32);
+        handshake.write_var_int(762);
+        handshake.write_string("play.example.com");
+        handshake.write_short(25565);
+        handshake.write_var_int(2);
+
+        let cases = [
+            (ConnectionState::Handshake, frame(0x00, handshake.as_ref())),
+            (ConnectionState::Play, frame(0x12, &42i64.to_be_bytes())),
+        ];
+
+        for (state, bytes) in cases {
+            let packet = Packet::decode(&bytes, state, ProtocolVersion::V1_19_4, None).await.unwrap();
+            assert_eq!(packet.raw_size, bytes.len());
+            assert_eq!(packet.encode(ProtocolVersion::V1_19_4).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn known_packs_are_read_as_written() {
+        let packs = vec![KnownPack {
+            namespace: "minecraft".to_string(),
+            id: "core".to_string(),
+            version: "1.20.2".to_string(),
+        }];
+
+        let mut packet = PacketWriter::create(32);
+        packet.write_known_packs(&packs);
+
+        let mut reader = PacketReader::create(packet.as_ref());
+        assert_eq!(reader.read_known_packs().unwrap(), packs);
+        assert_eq!(reader.left_to_read(), 0);
+    }
+
+    #[test]
+    fn too_many_known_packs_are_rejected() {
+        let mut packet = PacketWriter::create(8);
+        packet.write_var_int(MAX_KNOWN_PACKS as i32 + 1);
+
+        assert!(matches!(PacketReader::create(packet.as_ref()).read_known_packs(), Err(DecodingError::ArrayTooLarge)));
+    }
+
+    #[test]
+    fn out_of_range_spawn_positions_are_rejected() {
+        assert!(matches!(SpawnPosition::new(1 << 25, 64, 0, 0.0), Err(EncodingError::PositionOutOfRange(..))));
+        assert!(matches!(SpawnPosition::new(0, 2048, 0, 0.0), Err(EncodingError::PositionOutOfRange(..))));
+        assert!(matches!(SpawnPosition::new(0, 64, -(1 << 25) - 1, 0.0), Err(EncodingError::PositionOutOfRange(..))));
+        assert!(matches!(SpawnPosition::new(0, 64, 0, f32::NAN), Err(EncodingError::InvalidAngle(_))));
+    }
+
+    #[test]
+    fn spawn_angles_are_normalized() {
+        assert_eq!(SpawnPosition::new(0, 64, 0, -90.0).unwrap().angle(), 270.0);
+        assert_eq!(SpawnPosition::new(0, 64, 0, 450.0).unwrap().angle(), 90.0);
+    }
+
+    #[test]
+    fn spawn_positions_are_written_packed_with_their_angle() {
+        let spawn = SpawnPosition::new(-(1 << 25), -2048, (1 << 25) - 1, 45.0).unwrap();
+        let mut packet = PacketWriter::create(12);
+        packet.write_spawn_position(&spawn);
+
+        let mut reader = PacketReader::create(packet.as_ref());
+        assert_eq!(reader.read_position().unwrap(), (spawn.x(), spawn.y(), spawn.z()));
+        assert_eq!(reader.read_float().unwrap(), 45.0);
+    }
+
+    // reads optionals inside optionals for as long as they're present, returning how deep they went
+    fn read_nested_optionals(reader: &mut PacketReader) -> Result<usize, DecodingError> {
+        Ok(reader.read_optional(read_nested_optionals)?.map_or(0, |depth| depth + 1))
+    }
+
+    #[test]
+    fn nesting_beyond_the_limit_is_rejected() {
+        let nested = [1; 100_000];
+
+        let mut reader = PacketReader::create(&nested);
+        assert!(matches!(read_nested_optionals(&mut reader), Err(DecodingError::TooDeeplyNested)));
+
+        let mut reader = PacketReader::create(&nested);
+        reader.set_max_depth(3);
+        assert!(matches!(read_nested_optionals(&mut reader), Err(DecodingError::TooDeeplyNested)));
+    }
+
+    #[test]
+    fn nesting_up_to_the_limit_is_read() {
+        let mut nested = vec![1; 3];
+        nested.push(0);
+
+        let mut reader = PacketReader::create(&nested);
+        reader.set_max_depth(3);
+        assert_eq!(read_nested_optionals(&mut reader).unwrap(), 3);
+    }
+
+    #[test]
+    fn packet_types_report_the_direction_they_are_registered_in() {
+        for ids in PACKET_IDS.values() {
+            for packet_type in ids.serverbound.values() {
+                assert_eq!(packet_type.direction(), Direction::Serverbound, "{:?}", packet_type);
+            }
+
+            for packet_type in ids.clientbound.keys() {
+                assert_eq!(packet_type.direction(), Direction::Clientbound, "{:?}", packet_type);
+            }
+        }
+    }
+
+    #[test]
+    fn packet_type_directions_match_their_names() {
+        let registered = PACKET_IDS.values()
+            .flat_map(|ids| ids.serverbound.values().chain(ids.clientbound.keys()));
+
+        for packet_type in registered {
+            let name = format!("{:?}", packet_type);
+            let direction = if name.contains("Serverbound") { Direction::Serverbound } else { Direction::Clientbound };
+
+            assert!(name.contains("Serverbound") != name.contains("Clientbound"), "{}", name);
+            assert_eq!(packet_type.direction(), direction, "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn flushed_packets_are_length_prefixed() {
+        let mut packet = PacketWriter::create(256);
+        packet.write_var_int(0x12);
+        packet.write_all(&[7; 200]).unwrap();
+
+        let mut out = Vec::new();
+        packet.flush_to(&mut out).await.unwrap();
+
+        // 201 bytes take two bytes of varint
+        assert_eq!(out[..3], [0xC9, 0x01, 0x12]);
+        assert_eq!(out[3..], [7; 200]);
+    }
+
+    #[tokio::test]
+    async fn flushed_packets_below_the_threshold_are_left_uncompressed() {
+        let mut packet = PacketWriter::create(8);
+        packet.write_var_int(0x12);
+        packet.write_long(7);
+
+        let mut out = Vec::new();
+        packet.flush_to_compressed(&mut out, 256).await.unwrap();
+
+        let mut expected = vec![10, 0, 0x12];
+        expected.extend_from_slice(&7i64.to_be_bytes());
+        assert_eq!(out, expected);
+    }
+
+    // a packet of the id followed by data bytes, len() bytes in total
+    fn packet_of_size(size: usize) -> PacketWriter {
+        let mut packet = PacketWriter::create(size);
+        packet.write_var_int(0x05);
+        packet.write_all(&vec![b'a'; size - 1]).unwrap();
+        packet
+    }
 
-    Ok(())
-}
\ No newline at end of file
+    #[test]
+    fn packets_at_the_threshold_stay_uncompressed() {
+        let packet = packet_of_size(64);
+        let mut frame = PacketWriter::create(80);
+        packet.write_frame(&mut frame, Some(64)).unwrap();
+
+        // the length covers the data length of 0 and the packet
+        assert_eq!(frame.as_ref()[..3], [65, 0, 0x05]);
+        assert_eq!(frame.as_ref()[3..], [b'a'; 63]);
+    }
+
+    #[tokio::test]
+    async fn packets_above_the_threshold_are_compressed() {
+        let packet = packet_of_size(65);
+        let mut frame = PacketWriter::create(80);
+        packet.write_frame(&mut frame, Some(64)).unwrap();
+
+        let mut reader = PacketReader::create(frame.as_ref());
+        let length = reader.read_varint().unwrap();
+        assert_eq!(reader.read_varint().unwrap(), 65);
+        assert!(length < 65);
+
+        let decoded = Packet::decode(frame.as_ref(), ConnectionState::Play, ProtocolVersion::V1_19_4, Some(64)).await.unwrap();
+        assert_eq!(decoded.packet_type, PacketType::PlayServerboundChatMessage);
+        assert_eq!(decoded.data, [b'a'; 64]);
+    }
+
+    #[test]
+    fn a_threshold_of_zero_compresses_every_packet() {
+        let packet = packet_of_size(1);
+        let mut frame = PacketWriter::create(16);
+        packet.write_frame(&mut frame, Some(0)).unwrap();
+
+        let mut reader = PacketReader::create(frame.as_ref());
+        reader.read_varint().unwrap();
+        assert_eq!(reader.read_varint().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn compressed_packets_below_the_threshold_are_rejected() {
+        let packet = packet_of_size(65);
+        let mut frame = PacketWriter::create(80);
+        packet.write_frame(&mut frame, Some(64)).unwrap();
+
+        // a client must not compress what the threshold leaves uncompressed
+        let decoded = Packet::decode(frame.as_ref(), ConnectionState::Play, ProtocolVersion::V1_19_4, Some(256)).await;
+        assert!(matches!(decoded, Err(DecodingError::InvalidCompressedData)));
+    }
+
+    #[test]
+    fn vec3d_is_read_as_written() {
+        let positions = [(0.5, 64.0, -0.5), (-1234.125, -63.999, 29999999.75), (f64::MIN_POSITIVE, 1e-9, -0.0)];
+
+        for position in positions {
+            let mut packet = PacketWriter::create(24);
+            packet.write_vec3d(position.0, position.1, position.2);
+            assert_eq!(packet.len(), 24);
+
+            let mut reader = PacketReader::create(packet.as_ref());
+            assert_eq!(reader.read_vec3d().unwrap(), position);
+            assert_eq!(reader.left_to_read(), 0);
+        }
+
+        assert!(PacketReader::create(&[0; 23]).read_vec3d().is_err());
+    }
+
+    #[test]
+    fn five_byte_varints_up_to_the_i32_range_are_read() {
+        assert_eq!(PacketReader::create(&[0xFF, 0xFF, 0xFF, 0xFF, 0x07]).read_varint().unwrap(), i32::MAX);
+        assert_eq!(PacketReader::create(&[0xFF, 0xFF, 0xFF, 0xFF, 0x0F]).read_varint().unwrap(), -1);
+        assert_eq!(PacketReader::create(&[0x80, 0x80, 0x80, 0x80, 0x08]).read_varint().unwrap(), i32::MIN);
+    }
+
+    #[test]
+    fn five_byte_varints_beyond_the_i32_range_are_rejected() {
+        for last_byte in [0x10, 0x70, 0x7F] {
+            let varint = [0xFF, 0xFF, 0xFF, 0xFF, last_byte];
+            assert!(matches!(PacketReader::create(&varint).read_varint(), Err(DecodingError::VarIntTooBig)), "{:x}", last_byte);
+        }
+    }
+
+    #[test]
+    fn varlongs_are_read_as_written() {
+        let values = [(0, 1), (1, 1), (-1, 10), (300, 2), (i32::MAX as i64 + 1, 5), (i64::MAX, 9), (i64::MIN, 10)];
+
+        for (value, size) in values {
+            let mut packet = PacketWriter::create(10);
+            packet.write_var_long(value);
+            assert_eq!(packet.len(), size, "{}", value);
+
+            let mut reader = PacketReader::create(packet.as_ref());
+            assert_eq!(reader.read_var_long().unwrap(), value);
+            assert_eq!(reader.left_to_read(), 0);
+        }
+    }
+
+    #[test]
+    fn varlongs_beyond_the_i64_range_are_rejected() {
+        let mut too_long = [0xFF; 11];
+        too_long[10] = 0x01;
+        assert!(matches!(PacketReader::create(&too_long).read_var_long(), Err(DecodingError::VarLongTooBig)));
+
+        let mut overflowing = [0xFF; 10];
+        overflowing[9] = 0x02;
+        assert!(matches!(PacketReader::create(&overflowing).read_var_long(), Err(DecodingError::VarLongTooBig)));
+    }
+
+    #[test]
+    fn ints_and_floats_are_read_as_written() {
+        let mut packet = PacketWriter::create(32);
+        packet.write_int(i32::MIN);
+        packet.write_int(0x01020304);
+        packet.write_float(-0.15625);
+        packet.write_double(std::f64::consts::PI);
+        packet.write_double(f64::INFINITY);
+
+        assert_eq!(packet.as_ref()[..8], [0x80, 0, 0, 0, 1, 2, 3, 4]);
+        assert_eq!(packet.as_ref()[8..12], (-0.15625f32).to_be_bytes());
+
+        let mut reader = PacketReader::create(packet.as_ref());
+        assert_eq!(reader.read_int().unwrap(), i32::MIN);
+        assert_eq!(reader.read_int().unwrap(), 0x01020304);
+        assert_eq!(reader.read_float().unwrap(), -0.15625);
+        assert_eq!(reader.read_double().unwrap(), std::f64::consts::PI);
+        assert_eq!(reader.read_double().unwrap(), f64::INFINITY);
+        assert_eq!(reader.left_to_read(), 0);
+
+        assert!(PacketReader::create(&[0; 3]).read_int().is_err());
+        assert!(PacketReader::create(&[0; 7]).read_double().is_err());
+    }
+
+    #[test]
+    fn signed_and_unsigned_bytes_are_read_as_written() {
+        let mut packet = PacketWriter::create(4);
+        packet.write_byte(-1);
+        packet.write_byte(i8::MIN);
+        packet.write_ubyte(255);
+        packet.write_ubyte(0);
+
+        assert_eq!(packet.as_ref(), [0xFF, 0x80, 0xFF, 0x00]);
+
+        let mut reader = PacketReader::create(packet.as_ref());
+        assert_eq!(reader.read_byte().unwrap(), -1);
+        assert_eq!(reader.read_byte().unwrap(), i8::MIN);
+        assert_eq!(reader.read_ubyte().unwrap(), 255);
+        assert_eq!(reader.read_ubyte().unwrap(), 0);
+        assert!(matches!(reader.read_byte(), Err(DecodingError::PacketTooSmall)));
+    }
+
+    #[test]
+    fn truncated_longs_and_uuids_are_errors() {
+        assert!(PacketReader::create(&[1, 2, 3]).read_long().is_err());
+        assert!(PacketReader::create(&[0; 7]).read_long().is_err());
+        assert!(PacketReader::create(&[0; 15]).read_uuid().is_err());
+
+        let mut reader = PacketReader::create(&[0, 0, 0, 0, 0, 0, 1, 2]);
+        assert_eq!(reader.read_long().unwrap(), 0x0102);
+    }
+
+    #[test]
+    fn positions_are_read_as_written() {
+        let positions = [
+            (0, 0, 0),
+            (-1, -1, -1),
+            (18357644, 831, -20882616),
+            // the limits of the 26 and 12 bit fields
+            (33554431, 2047, 33554431),
+            (-33554432, -2048, -33554432),
+        ];
+
+        for position in positions {
+            let mut packet = PacketWriter::create(8);
+            packet.write_position(position.0, position.1, position.2);
+
+            assert_eq!(PacketReader::create(packet.as_ref()).read_position().unwrap(), position);
+        }
+    }
+
+    #[test]
+    fn positions_are_packed_as_x_z_y() {
+        let mut packet = PacketWriter::create(8);
+        packet.write_position(18357644, 831, -20882616);
+
+        // the example from the protocol documentation
+        assert_eq!(packet.as_ref(), 0x4607632C15B4833Fu64.to_be_bytes());
+    }
+
+    #[test]
+    fn byte_arrays_are_read_as_written() {
+        let mut packet = PacketWriter::create(16);
+        packet.write_byte_array(&[]);
+        packet.write_byte_array(&[1, 2, 3]);
+        packet.write_all(&[9, 9]).unwrap();
+
+        assert_eq!(packet.as_ref(), [0, 3, 1, 2, 3, 9, 9]);
+
+        let mut reader = PacketReader::create(packet.as_ref());
+        assert_eq!(reader.read_byte_array(0).unwrap(), [0u8; 0]);
+        assert_eq!(reader.read_byte_array(3).unwrap(), [1, 2, 3]);
+        assert_eq!(reader.read_remaining(), [9, 9]);
+        assert_eq!(reader.read_remaining(), [0u8; 0]);
+    }
+
+    #[test]
+    fn byte_arrays_beyond_the_limit_are_rejected() {
+        assert!(matches!(PacketReader::create(&[3, 1, 2, 3]).read_byte_array(2), Err(DecodingError::ArrayTooLarge)));
+        // a negative length
+        assert!(matches!(PacketReader::create(&[0xFF, 0xFF, 0xFF, 0xFF, 0x0F]).read_byte_array(16), Err(DecodingError::ArrayTooLarge)));
+        // a length the packet is too short for, which must not be allocated
+        assert!(PacketReader::create(&[0xFF, 0xFF, 0xFF, 0xFF, 0x07, 1]).read_byte_array(usize::MAX).is_err());
+    }
+
+    #[test]
+    fn peeked_varints_are_not_consumed() {
+        let mut reader = PacketReader::create(&[0xAC, 0x02, 0x07]);
+        assert_eq!(reader.peek_varint().unwrap(), Some((300, 2)));
+        assert_eq!(reader.read_varint().unwrap(), 300);
+
+        let mut reader = PacketReader::create(&[0xAC]);
+        assert_eq!(reader.peek_varint().unwrap(), None);
+        assert_eq!(reader.left_to_read(), 1);
+
+        let mut reader = PacketReader::create(&[0xFF; 6]);
+        assert!(reader.peek_varint().is_err());
+    }
+
+    #[test]
+    fn string_lengths_are_counted_in_utf16_code_units() {
+        // 1 byte, 2 bytes, 3 bytes and 4 bytes as 2 code units
+        let cases = [("a", 16), ("é", 16), ("日", 16), ("😀", 8)];
+
+        for (unit, fitting) in cases {
+            for (count, fits) in [(fitting, true), (fitting + 1, false)] {
+                let str = unit.repeat(count);
+
+                let mut packet = PacketWriter::create(64);
+                packet.write_string(&str);
+                let read = PacketReader::create(packet.as_ref()).read_string(16);
+                assert_eq!(read.is_ok(), fits, "{} x {}", unit, count);
+
+                let written = PacketWriter::create(64).write_string_checked(&str, 16);
+                assert_eq!(written.is_ok(), fits, "{} x {}", unit, count);
+            }
+        }
+
+        let mut packet = PacketWriter::create(64);
+        packet.write_string("日本語😀");
+        assert_eq!(PacketReader::create(packet.as_ref()).read_string(5).unwrap(), "日本語😀");
+    }
+
+    #[test]
+    fn shorts_and_doubles_are_big_endian() {
+        let mut packet = PacketWriter::create(32);
+        packet.write_short(0x1234);
+        packet.write_short(u16::MAX);
+        packet.write_double(-2.5);
+        packet.write_double(f64::MIN_POSITIVE);
+
+        assert_eq!(packet.as_ref()[..4], [0x12, 0x34, 0xFF, 0xFF]);
+        assert_eq!(packet.as_ref()[4..12], [0xC0, 0x04, 0, 0, 0, 0, 0, 0]);
+
+        let mut reader = PacketReader::create(packet.as_ref());
+        assert_eq!(reader.read_short().unwrap(), 0x1234);
+        assert_eq!(reader.read_short().unwrap(), u16::MAX);
+        assert_eq!(reader.read_double().unwrap(), -2.5);
+        assert_eq!(reader.read_double().unwrap(), f64::MIN_POSITIVE);
+        assert_eq!(reader.left_to_read(), 0);
+
+        assert!(PacketReader::create(&[0; 1]).read_short().is_err());
+    }
+
+    const VARINT_BOUNDARIES: [i32; 8] = [0, 127, 128, 16383, 16384, i32::MIN, -1, i32::MAX];
+
+    #[test]
+    fn varints_at_the_boundaries_are_read_as_written() {
+        for value in VARINT_BOUNDARIES {
+            let mut packet = PacketWriter::create(5);
+            packet.write_var_int(value);
+
+            let expected_size = match value {
+                0..=127 => 1,
+                128..=16383 => 2,
+                16384..=2097151 => 3,
+                2097152..=268435455 => 4,
+                // negative values always take all five
+                _ => 5,
+            };
+            assert_eq!(packet.len(), expected_size, "{}", value);
+
+            let mut reader = PacketReader::create(packet.as_ref());
+            assert_eq!(reader.read_varint_with_size().unwrap(), (value, expected_size));
+            assert_eq!(reader.left_to_read(), 0);
+        }
+    }
+
+    #[test]
+    fn varlongs_at_the_boundaries_are_read_as_written() {
+        let values = VARINT_BOUNDARIES.iter().map(|&value| value as i64).chain([i64::MIN, i64::MAX, 1 << 35]);
+
+        for value in values {
+            let mut packet = PacketWriter::create(10);
+            packet.write_var_long(value);
+
+            let expected_size = if value < 0 { 10 } else { (64 - value.leading_zeros() as usize).max(1).div_ceil(7) };
+            assert_eq!(packet.len(), expected_size, "{}", value);
+
+            let mut reader = PacketReader::create(packet.as_ref());
+            assert_eq!(reader.read_var_long().unwrap(), value);
+            assert_eq!(reader.left_to_read(), 0);
+        }
+    }
+
+    #[test]
+    fn overlong_varints_are_rejected() {
+        // zero with continuation bits up to a sixth byte
+        let overlong = [0x80, 0x80, 0x80, 0x80, 0x80, 0x00];
+        assert!(matches!(PacketReader::create(&overlong).read_varint(), Err(DecodingError::VarIntTooBig)));
+
+        let overlong = [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01];
+        assert!(matches!(PacketReader::create(&overlong).read_varint(), Err(DecodingError::VarIntTooBig)));
+    }
+
+    #[tokio::test]
+    async fn async_varints_are_the_bytes_of_the_writer() {
+        let values = VARINT_BOUNDARIES.into_iter().chain((-1000..1000).map(|value| value * 7919)).chain([2097151, 2097152, 268435455, 268435456]);
+
+        for value in values {
+            let mut written = Vec::new();
+            write_var_int(&mut written, value).await.unwrap();
+
+            let mut packet = PacketWriter::create(5);
+            packet.write_var_int(value);
+            assert_eq!(written, packet.as_ref(), "{}", value);
+        }
+    }
+
+    #[tokio::test]
+    async fn complete_frames_too_short_for_a_packet_are_invalid() {
+        let frames: [&[u8]; 3] = [&[0x00], &[0x01, 0x80], &[0x02, 0x80, 0x80]];
+
+        for frame in frames {
+            let decoded = Packet::decode(frame, ConnectionState::Status, ProtocolVersion::V1_19_4, None).await;
+            assert!(matches!(decoded, Err(DecodingError::InvalidPacket)), "{:?}", frame);
+        }
+
+        // the data length of the compressed format cut off
+        let decoded = Packet::decode(&[0x01, 0x80], ConnectionState::Status, ProtocolVersion::V1_19_4, Some(256)).await;
+        assert!(matches!(decoded, Err(DecodingError::InvalidPacket)));
+
+        // frames that didn't fully arrive are still waited for
+        let decoded = Packet::decode(&[0x02, 0x80], ConnectionState::Status, ProtocolVersion::V1_19_4, None).await;
+        assert!(matches!(decoded, Err(DecodingError::PacketTooSmall)));
+    }
+
+    #[test]
+    fn the_single_byte_shortcut_reads_what_the_loop_does() {
+        let varints: [&[u8]; 6] = [&[0x00], &[0x7F], &[0x80, 0x01], &[0xFF, 0x01], &[0xAC, 0x02, 0x7F], &[0x80]];
+
+        for varint in varints {
+            let mut shortcut = PacketReader::create(varint);
+            let mut bytewise = PacketReader::create(varint);
+
+            assert_eq!(
+                format!("{:?}", shortcut.read_varint()),
+                format!("{:?}", bytewise.read_varint_bytewise()),
+                "{:?}", varint
+            );
+            assert_eq!(shortcut.left_to_read(), bytewise.left_to_read(), "{:?}", varint);
+        }
+
+        assert_eq!(PacketReader::create(&[0x00]).read_varint().unwrap(), 0);
+        assert_eq!(PacketReader::create(&[0x7F]).read_varint().unwrap(), 127);
+        assert_eq!(PacketReader::create(&[0x80, 0x01]).read_varint().unwrap(), 128);
+    }
+
+    #[test]
+    fn every_supported_version_uses_its_own_packet_ids() {
+        let v1_19_4 = PacketIds::of(ProtocolVersion::V1_19_4);
+        let v1_20 = PacketIds::of(ProtocolVersion::V1_20);
+
+        assert!(std::ptr::eq(v1_19_4, &PACKET_IDS[&ProtocolVersion::V1_19_4]));
+        assert!(std::ptr::eq(v1_20, &PACKET_IDS[&ProtocolVersion::V1_20]));
+        assert!(!std::ptr::eq(v1_19_4, v1_20));
+
+        // the experimental ones borrow the latest
+        let unknown = PacketIds::of(ProtocolVersion::Unknown(9999));
+        assert!(std::ptr::eq(unknown, &PACKET_IDS[&ProtocolVersion::LATEST_SUPPORTED]));
+    }
+
+    #[tokio::test]
+    async fn packets_are_read_and_written_with_the_ids_of_their_version() {
+        // a version that moved the status packets
+        let moved = PacketIds::create(
+            HashMap::from([(PacketTypeKey { state: ConnectionState::Status, id: 0x05 }, PacketType::StatusServerboundPing)]),
+            HashMap::from([(PacketType::StatusClientboundPong, 0x07)]),
+        );
+
+        let ping = Packet { data: 42i64.to_be_bytes().to_vec(), raw_size: 0, packet_type: PacketType::StatusServerboundPing };
+        let frame = ping.encode_with_ids(&moved, None).unwrap();
+        assert_eq!(frame[1], 0x05);
+        assert_ne!(frame, ping.encode(ProtocolVersion::V1_19_4).unwrap());
+
+        let read = Packet::read(&mut PacketReader::create(&frame), Direction::Serverbound, ConnectionState::Status, &moved, None).unwrap();
+        assert_eq!(read.packet_type, PacketType::StatusServerboundPing);
+        assert_eq!(read.data, 42i64.to_be_bytes());
+
+        // the same frame means nothing to 1.19.4
+        let decoded = Packet::decode(&frame, ConnectionState::Status, ProtocolVersion::V1_19_4, None).await;
+        assert!(matches!(decoded, Err(DecodingError::InvalidPacketId(0x05, ConnectionState::Status))));
+
+        let pong = Packet { data: Vec::new(), raw_size: 0, packet_type: PacketType::StatusClientboundPong };
+        assert_eq!(pong.encode_with_ids(&moved, None).unwrap(), [1, 0x07]);
+        assert_eq!(pong.encode(ProtocolVersion::V1_20).unwrap(), [1, 0x01]);
+    }
+}
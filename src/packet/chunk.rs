@@ -0,0 +1,92 @@
+use std::io::Write;
+
+use crate::nbt::NbtWriter;
+use crate::packet::{PacketType, PacketWriter};
+use crate::protocol::ProtocolVersion;
+
+// vertical extent of the overworld
+pub const WORLD_MIN_Y: i32 = -64;
+pub const WORLD_HEIGHT: i32 = 384;
+const SECTION_COUNT: i32 = WORLD_HEIGHT / 16;
+
+// the flat chunks are solid stone from y = 0 up to the top of this section
+const GROUND_SECTION: i32 = -WORLD_MIN_Y / 16;
+const GROUND_HEIGHT: i32 = (GROUND_SECTION + 1) * 16;
+
+const AIR: i32 = 0;
+const STONE: i32 = 1;
+const PLAINS: i32 = 0;
+
+impl PacketWriter {
+    // a chunk that is air except for a single section of stone, without any light data
+    pub fn write_flat_chunk(&mut self, protocol: ProtocolVersion, x: i32, z: i32) {
+        self.write_packet_type(PacketType::PlayClientboundChunkDataAndLight, protocol);
+        self.write_int(x);
+        self.write_int(z);
+
+        let mut heightmaps = NbtWriter::create();
+        heightmaps.begin_root();
+        heightmaps.write_long_array_tag("MOTION_BLOCKING", &pack_heightmap(GROUND_HEIGHT));
+        heightmaps.end_compound();
+        self.write_all(heightmaps.as_ref()).unwrap();
+
+        let mut sections = PacketWriter::create(SECTION_COUNT as usize * 8);
+        for section in 0..SECTION_COUNT {
+            if section == GROUND_SECTION {
+                sections.write_single_valued_section(4096, STONE);
+            } else {
+                sections.write_single_valued_section(0, AIR);
+            }
+        }
+        self.write_byte_array(sections.as_ref());
+
+        self.write_var_int(0); // block entities
+
+        // 1.20 trusts the edges of every light update and dropped the field
+        if protocol == ProtocolVersion::V1_19_4 {
+            self.write_boolean(true); // trust edges
+        }
+
+        self.write_var_int(0); // sky light mask
+        self.write_var_int(0); // block light mask
+        self.write_var_int(0); // empty sky light mask
+        self.write_var_int(0); // empty block light mask
+        self.write_var_int(0); // sky light arrays
+        self.write_var_int(0); // block light arrays
+    }
+
+    // the chunk the client loads the others around
+    pub fn write_center_chunk(&mut self, protocol: ProtocolVersion, x: i32, z: i32) {
+        self.write_packet_type(PacketType::PlayClientboundSetCenterChunk, protocol);
+        self.write_var_int(x);
+        self.write_var_int(z);
+    }
+
+    // a section holding only one block state, and one biome, which needs no bits per entry
+    fn write_single_valued_section(&mut self, block_count: i16, block_state: i32) {
+        self.write_short(block_count as u16);
+
+        self.write_ubyte(0); // bits per block
+        self.write_var_int(block_state);
+        self.write_var_int(0); // data array length
+
+        self.write_ubyte(0); // bits per biome
+        self.write_var_int(PLAINS);
+        self.write_var_int(0); // data array length
+    }
+}
+
+// the same height for every column, as entries of 9 bits of which 7 fit in a long
+fn pack_heightmap(height: i32) -> Vec<i64> {
+    const BITS: usize = 9;
+    const PER_LONG: usize = 64 / BITS;
+
+    let height = (height - WORLD_MIN_Y) as i64;
+    let mut longs = vec![0i64; 256usize.div_ceil(PER_LONG)];
+
+    for column in 0..256 {
+        longs[column / PER_LONG] |= height << ((column % PER_LONG) * BITS);
+    }
+
+    longs
+}
@@ -0,0 +1,126 @@
+use crate::packet::{DecodingError, PacketReader, PacketWriter};
+
+// marks the end of the metadata in place of an index
+const TERMINATOR: u8 = 0xFF;
+
+// type ids of the supported metadata values
+const TYPE_BYTE: i32 = 0;
+const TYPE_VAR_INT: i32 = 1;
+const TYPE_FLOAT: i32 = 3;
+const TYPE_STRING: i32 = 4;
+const TYPE_BOOLEAN: i32 = 8;
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum MetadataValue {
+    Byte(i8),
+    VarInt(i32),
+    Float(f32),
+    String(String),
+    Boolean(bool),
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct MetadataEntry {
+    pub index: u8,
+    pub value: MetadataValue,
+}
+
+impl<'a> PacketReader<'a> {
+    pub fn read_metadata(&mut self) -> Result<Vec<MetadataEntry>, DecodingError> {
+        let mut entries = Vec::new();
+
+        loop {
+            let index = self.read_ubyte()?;
+            if index == TERMINATOR {
+                return Ok(entries);
+            }
+
+            let value = match self.read_varint()? {
+                TYPE_BYTE => MetadataValue::Byte(self.read_byte()?),
+                TYPE_VAR_INT => MetadataValue::VarInt(self.read_varint()?),
+                TYPE_FLOAT => MetadataValue::Float(self.read_float()?),
+                TYPE_STRING => MetadataValue::String(self.read_string(32767)?),
+                TYPE_BOOLEAN => MetadataValue::Boolean(self.read_boolean()?),
+                other => return Err(DecodingError::UnknownMetadataType(other))
+            };
+
+            entries.push(MetadataEntry { index, value });
+        }
+    }
+}
+
+impl PacketWriter {
+    pub fn write_metadata(&mut self, entries: &[MetadataEntry]) {
+        for entry in entries {
+            self.write_ubyte(entry.index);
+
+            match &entry.value {
+                MetadataValue::Byte(value) => {
+                    self.write_var_int(TYPE_BYTE);
+                    self.write_byte(*value);
+                }
+                MetadataValue::VarInt(value) => {
+                    self.write_var_int(TYPE_VAR_INT);
+                    self.write_var_int(*value);
+                }
+                MetadataValue::Float(value) => {
+                    self.write_var_int(TYPE_FLOAT);
+                    self.write_float(*value);
+                }
+                MetadataValue::String(value) => {
+                    self.write_var_int(TYPE_STRING);
+                    self.write_string(value);
+                }
+                MetadataValue::Boolean(value) => {
+                    self.write_var_int(TYPE_BOOLEAN);
+                    self.write_boolean(*value);
+                }
+            }
+        }
+
+        self.write_ubyte(TERMINATOR);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata_is_read_as_written() {
+        let entries = [
+            MetadataEntry { index: 0, value: MetadataValue::Byte(-0x80) },
+            MetadataEntry { index: 1, value: MetadataValue::VarInt(300) },
+            MetadataEntry { index: 2, value: MetadataValue::String("Steve".to_string()) },
+            MetadataEntry { index: 3, value: MetadataValue::Boolean(true) },
+            MetadataEntry { index: 9, value: MetadataValue::Float(20.0) },
+        ];
+
+        let mut packet = PacketWriter::create(32);
+        packet.write_metadata(&entries);
+        packet.write_ubyte(7);
+
+        // the index and type of the first entry, and the terminator after the last
+        assert_eq!(packet.as_ref()[..3], [0, TYPE_BYTE as u8, 0x80]);
+        assert_eq!(packet.as_ref()[packet.len() - 2..], [TERMINATOR, 7]);
+
+        let mut reader = PacketReader::create(packet.as_ref());
+        assert_eq!(reader.read_metadata().unwrap(), entries);
+        assert_eq!(reader.left_to_read(), 1);
+    }
+
+    #[test]
+    fn empty_metadata_is_only_the_terminator() {
+        let mut packet = PacketWriter::create(1);
+        packet.write_metadata(&[]);
+
+        assert_eq!(packet.as_ref(), [TERMINATOR]);
+        assert_eq!(PacketReader::create(packet.as_ref()).read_metadata().unwrap(), []);
+    }
+
+    #[test]
+    fn unknown_and_unterminated_metadata_are_rejected() {
+        assert!(matches!(PacketReader::create(&[0, 2, 0]).read_metadata(), Err(DecodingError::UnknownMetadataType(2))));
+        assert!(PacketReader::create(&[0, 1, 5]).read_metadata().is_err());
+    }
+}
@@ -0,0 +1,135 @@
+use uuid::Uuid;
+
+use crate::packet::{DecodingError, PacketReader, PacketWriter};
+
+// the fields of a packet, after its packet id
+pub trait Encodable {
+    fn encode(&self, writer: &mut PacketWriter);
+}
+
+pub trait Decodable: Sized {
+    fn decode(reader: &mut PacketReader) -> Result<Self, DecodingError>;
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct Handshake {
+    pub protocol_version: i32,
+    // may carry forwarding data after the host name, which makes it this long
+    pub host: String,
+    pub port: u16,
+    pub next_state: i32,
+}
+
+impl Encodable for Handshake {
+    fn encode(&self, writer: &mut PacketWriter) {
+        writer.write_var_int(self.protocol_version);
+        writer.write_string(&self.host);
+        writer.write_short(self.port);
+        writer.write_var_int(self.next_state);
+    }
+}
+
+impl Decodable for Handshake {
+    fn decode(reader: &mut PacketReader) -> Result<Self, DecodingError> {
+        Ok(Handshake {
+            protocol_version: reader.read_varint()?,
+            host: reader.read_string(32767)?,
+            port: reader.read_short()?,
+            next_state: reader.read_varint()?,
+        })
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct StatusRequest;
+
+impl Encodable for StatusRequest {
+    fn encode(&self, _writer: &mut PacketWriter) {}
+}
+
+impl Decodable for StatusRequest {
+    fn decode(_reader: &mut PacketReader) -> Result<Self, DecodingError> {
+        Ok(StatusRequest)
+    }
+}
+
+// the ping, and the pong echoing its payload
+#[derive(PartialEq, Clone, Debug)]
+pub struct StatusPing {
+    pub payload: i64,
+}
+
+impl Encodable for StatusPing {
+    fn encode(&self, writer: &mut PacketWriter) {
+        writer.write_long(self.payload);
+    }
+}
+
+impl Decodable for StatusPing {
+    fn decode(reader: &mut PacketReader) -> Result<Self, DecodingError> {
+        Ok(StatusPing { payload: reader.read_long()? })
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct LoginStart {
+    pub name: String,
+    pub uuid: Option<Uuid>,
+}
+
+impl Encodable for LoginStart {
+    fn encode(&self, writer: &mut PacketWriter) {
+        writer.write_string(&self.name);
+        writer.write_boolean(self.uuid.is_some());
+        if let Some(uuid) = self.uuid {
+            writer.write_uuid(uuid);
+        }
+    }
+}
+
+impl Decodable for LoginStart {
+    fn decode(reader: &mut PacketReader) -> Result<Self, DecodingError> {
+        Ok(LoginStart {
+            name: reader.read_string(16)?,
+            uuid: reader.read_optional(|reader| reader.read_uuid())?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip<T: Encodable + Decodable + PartialEq + std::fmt::Debug>(payload: T) {
+        let mut writer = PacketWriter::create(64);
+        payload.encode(&mut writer);
+
+        let mut reader = PacketReader::create(writer.as_ref());
+        assert_eq!(T::decode(&mut reader).unwrap(), payload);
+        assert_eq!(reader.left_to_read(), 0);
+    }
+
+    #[test]
+    fn payloads_are_decoded_as_encoded() {
+        roundtrip(Handshake { protocol_version: 762, host: "play.example.com".to_string(), port: 25565, next_state: 2 });
+        roundtrip(StatusRequest);
+        roundtrip(StatusPing { payload: i64::MIN });
+        roundtrip(LoginStart { name: "Steve".to_string(), uuid: None });
+        roundtrip(LoginStart { name: "Notch".to_string(), uuid: Some(Uuid::from_u128(0x069a79f444e94726a5befca90e38aaf5)) });
+    }
+
+    #[test]
+    fn handshakes_have_the_wire_layout() {
+        let mut writer = PacketWriter::create(16);
+        Handshake { protocol_version: 763, host: "ab".to_string(), port: 25565, next_state: 1 }.encode(&mut writer);
+
+        assert_eq!(writer.as_ref(), [0xFB, 0x05, 2, b'a', b'b', 0x63, 0xDD, 1]);
+    }
+
+    #[test]
+    fn truncated_payloads_fail_to_decode() {
+        assert!(Handshake::decode(&mut PacketReader::create(&[0xFB, 0x05, 2, b'a'])).is_err());
+        assert!(StatusPing::decode(&mut PacketReader::create(&[0; 7])).is_err());
+        assert!(LoginStart::decode(&mut PacketReader::create(&[5, b'S', b't', b'e', b'v', b'e', 1, 0])).is_err());
+    }
+}
@@ -0,0 +1,136 @@
+use uuid::Uuid;
+
+use crate::auth::ProfileProperty;
+use crate::packet::{PacketType, PacketWriter};
+use crate::protocol::ProtocolVersion;
+
+// actions of a player info update, each one adds its fields to every entry in this order
+pub const ADD_PLAYER: u8 = 0x01;
+pub const INITIALIZE_CHAT: u8 = 0x02;
+pub const UPDATE_GAME_MODE: u8 = 0x04;
+pub const UPDATE_LISTED: u8 = 0x08;
+pub const UPDATE_LATENCY: u8 = 0x10;
+pub const UPDATE_DISPLAY_NAME: u8 = 0x20;
+
+#[derive(Clone, Debug)]
+pub struct PlayerInfoEntry {
+    pub uuid: Uuid,
+    pub name: String,
+    pub properties: Vec<ProfileProperty>,
+    pub game_mode: i32,
+    pub listed: bool,
+    pub latency: i32,
+}
+
+impl PacketWriter {
+    // chat sessions and display names aren't supported, their actions are written as absent
+    pub fn write_player_info_update(&mut self, protocol: ProtocolVersion, actions: u8, entries: &[PlayerInfoEntry]) {
+        self.write_packet_type(PacketType::PlayClientboundPlayerInfoUpdate, protocol);
+        self.write_ubyte(actions);
+        self.write_var_int(entries.len() as i32);
+
+        for entry in entries {
+            self.write_uuid(entry.uuid);
+
+            if actions & ADD_PLAYER != 0 {
+                self.write_string(&entry.name);
+                self.write_properties(&entry.properties);
+            }
+
+            if actions & INITIALIZE_CHAT != 0 {
+                self.write_boolean(false); // no chat session
+            }
+
+            if actions & UPDATE_GAME_MODE != 0 {
+                self.write_var_int(entry.game_mode);
+            }
+
+            if actions & UPDATE_LISTED != 0 {
+                self.write_boolean(entry.listed);
+            }
+
+            if actions & UPDATE_LATENCY != 0 {
+                self.write_var_int(entry.latency);
+            }
+
+            if actions & UPDATE_DISPLAY_NAME != 0 {
+                self.write_boolean(false); // no display name
+            }
+        }
+    }
+
+    pub fn write_properties(&mut self, properties: &[ProfileProperty]) {
+        self.write_var_int(properties.len() as i32);
+
+        for property in properties {
+            self.write_string(&property.name);
+            self.write_string(&property.value);
+            self.write_boolean(property.signature.is_some());
+            if let Some(signature) = &property.signature {
+                self.write_string(signature);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::PacketReader;
+
+    fn steve() -> PlayerInfoEntry {
+        PlayerInfoEntry {
+            uuid: Uuid::from_u128(0x069a79f444e94726a5befca90e38aaf5),
+            name: "Steve".to_string(),
+            properties: vec![ProfileProperty { name: "textures".to_string(), value: "e30=".to_string(), signature: Some("c2ln".to_string()) }],
+            game_mode: 1,
+            listed: true,
+            latency: 25,
+        }
+    }
+
+    #[test]
+    fn added_players_are_written_with_their_profile_and_listing() {
+        let mut packet = PacketWriter::create(64);
+        packet.write_player_info_update(ProtocolVersion::V1_19_4, ADD_PLAYER | UPDATE_LISTED, &[steve()]);
+
+        let mut reader = PacketReader::create(packet.as_ref());
+        assert_eq!(reader.read_varint().unwrap(), 0x3A);
+        assert_eq!(reader.read_ubyte().unwrap(), 0x09);
+        assert_eq!(reader.read_varint().unwrap(), 1);
+        assert_eq!(reader.read_uuid().unwrap(), steve().uuid);
+
+        assert_eq!(reader.read_string(16).unwrap(), "Steve");
+        assert_eq!(reader.read_varint().unwrap(), 1);
+        assert_eq!(reader.read_string(64).unwrap(), "textures");
+        assert_eq!(reader.read_string(64).unwrap(), "e30=");
+        assert!(reader.read_boolean().unwrap());
+        assert_eq!(reader.read_string(64).unwrap(), "c2ln");
+
+        assert!(reader.read_boolean().unwrap());
+        assert_eq!(reader.left_to_read(), 0);
+    }
+
+    #[test]
+    fn actions_are_written_in_the_order_of_their_flags() {
+        let mut packet = PacketWriter::create(64);
+        let actions = INITIALIZE_CHAT | UPDATE_GAME_MODE | UPDATE_LISTED | UPDATE_LATENCY | UPDATE_DISPLAY_NAME;
+        packet.write_player_info_update(ProtocolVersion::V1_19_4, actions, &[steve(), steve()]);
+
+        let mut reader = PacketReader::create(packet.as_ref());
+        reader.read_varint().unwrap();
+        assert_eq!(reader.read_ubyte().unwrap(), 0x3E);
+        assert_eq!(reader.read_varint().unwrap(), 2);
+
+        for _ in 0..2 {
+            assert_eq!(reader.read_uuid().unwrap(), steve().uuid);
+            assert!(!reader.read_boolean().unwrap());
+            assert_eq!(reader.read_varint().unwrap(), 1);
+            assert!(reader.read_boolean().unwrap());
+            assert_eq!(reader.read_varint().unwrap(), 25);
+            assert!(!reader.read_boolean().unwrap());
+        }
+
+        assert_eq!(reader.left_to_read(), 0);
+    }
+}
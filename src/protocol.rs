@@ -0,0 +1,87 @@
+use crate::packet::PacketIds;
+
+#[allow(non_camel_case_types)]
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum ProtocolVersion {
+    V1_19_4,
+    V1_20,
+    V1_20_2,
+    Unknown(i32),
+}
+
+impl ProtocolVersion {
+    // reported to clients of unsupported versions, its packet ids are used for unknown ones
+    pub const LATEST_SUPPORTED: ProtocolVersion = ProtocolVersion::V1_20;
+
+    pub fn from_i32(protocol: i32) -> ProtocolVersion {
+        match protocol {
+            762 => ProtocolVersion::V1_19_4,
+            763 => ProtocolVersion::V1_20,
+            764 => ProtocolVersion::V1_20_2,
+            other => ProtocolVersion::Unknown(other)
+        }
+    }
+
+    pub fn as_i32(&self) -> i32 {
+        match self {
+            ProtocolVersion::V1_19_4 => 762,
+            ProtocolVersion::V1_20 => 763,
+            ProtocolVersion::V1_20_2 => 764,
+            ProtocolVersion::Unknown(protocol) => *protocol
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            ProtocolVersion::V1_19_4 => "1.19.4".to_string(),
+            ProtocolVersion::V1_20 => "1.20.1".to_string(),
+            ProtocolVersion::V1_20_2 => "1.20.2".to_string(),
+            ProtocolVersion::Unknown(protocol) => format!("unknown ({})", protocol)
+        }
+    }
+
+    pub fn is_supported(&self) -> bool {
+        PacketIds::exist_for(*self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_protocol_numbers_map_to_their_versions() {
+        assert_eq!(ProtocolVersion::from_i32(762), ProtocolVersion::V1_19_4);
+        assert_eq!(ProtocolVersion::from_i32(763), ProtocolVersion::V1_20);
+        assert_eq!(ProtocolVersion::from_i32(764), ProtocolVersion::V1_20_2);
+
+        for version in [ProtocolVersion::V1_19_4, ProtocolVersion::V1_20, ProtocolVersion::V1_20_2] {
+            assert_eq!(ProtocolVersion::from_i32(version.as_i32()), version);
+        }
+    }
+
+    #[test]
+    fn unknown_protocol_numbers_are_kept() {
+        let unknown = ProtocolVersion::from_i32(47);
+
+        assert_eq!(unknown, ProtocolVersion::Unknown(47));
+        assert_eq!(unknown.as_i32(), 47);
+        assert_eq!(unknown.name(), "unknown (47)");
+        assert!(!unknown.is_supported());
+    }
+
+    #[test]
+    fn only_versions_with_packet_ids_are_supported() {
+        assert!(ProtocolVersion::V1_19_4.is_supported());
+        assert!(ProtocolVersion::V1_20.is_supported());
+        assert!(ProtocolVersion::LATEST_SUPPORTED.is_supported());
+        assert!(!ProtocolVersion::V1_20_2.is_supported());
+    }
+
+    #[test]
+    fn protocol_numbers_are_supported_through_their_version() {
+        let supported: Vec<i32> = (0..1000).filter(|&protocol| ProtocolVersion::from_i32(protocol).is_supported()).collect();
+
+        assert_eq!(supported, [762, 763]);
+    }
+}
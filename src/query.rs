@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::net::UdpSocket;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::protocol::ProtocolVersion;
+
+const MAGIC: [u8; 2] = [0xFE, 0xFD];
+const TYPE_HANDSHAKE: u8 = 9;
+const TYPE_STAT: u8 = 0;
+const CHALLENGE_LIFETIME: Duration = Duration::from_secs(30);
+
+struct Challenge {
+    token: i32,
+    issued_at: Instant,
+}
+
+pub struct QueryServer {
+    socket: UdpSocket,
+    server_address: SocketAddr,
+    config: Arc<Config>,
+    challenges: HashMap<SocketAddr, Challenge>,
+}
+
+impl QueryServer {
+    pub async fn run(&mut self) {
+        let mut buf = [0u8; 1460];
+
+        loop {
+            let (len, from) = match self.socket.recv_from(&mut buf).await {
+                Ok(received) => received,
+                Err(e) => {
+                    warn!("query: failed to receive: {}", e);
+                    continue;
+                }
+            };
+
+            if let Some(response) = self.handle(&buf[..len], from) {
+                if let Err(e) = self.socket.send_to(&response, from).await {
+                    warn!("query: failed to respond to {}: {}", from, e);
+                }
+            }
+        }
+    }
+
+    fn handle(&mut self, request: &[u8], from: SocketAddr) -> Option<Vec<u8>> {
+        if request.len() < 7 || request[0..2] != MAGIC {
+            return None;
+        }
+
+        let packet_type = request[2];
+        let session_id = i32::from_be_bytes(request[3..7].try_into().unwrap()) & 0x0F0F0F0F;
+        let payload = &request[7..];
+
+        let mut response = vec![packet_type];
+        response.extend_from_slice(&session_id.to_be_bytes());
+
+        match packet_type {
+            TYPE_HANDSHAKE => {
+                let token = self.issue_challenge(from);
+                write_string(&mut response, &token.to_string());
+            }
+            TYPE_STAT => {
+                if payload.len() < 4 || !self.check_challenge(from, &payload[0..4]) {
+                    return None;
+                }
+
+                if payload.len() >= 8 {
+                    self.write_full_stat(&mut response);
+                } else {
+                    self.write_basic_stat(&mut response);
+                }
+            }
+            _ => return None
+        }
+
+        Some(response)
+    }
+
+    fn issue_challenge(&mut self, from: SocketAddr) -> i32 {
+        let now = Instant::now();
+        self.challenges.retain(|_, challenge| now.duration_since(challenge.issued_at) < CHALLENGE_LIFETIME);
+
+        let token = (Uuid::new_v4().as_u64_pair().0 as i32) & 0x7FFFFFFF;
+        self.challenges.insert(from, Challenge { token, issued_at: now });
+
+        token
+    }
+
+    fn check_challenge(&self, from: SocketAddr, token: &[u8]) -> bool {
+        let token = i32::from_be_bytes(token.try_into().unwrap());
+
+        match self.challenges.get(&from) {
+            Some(challenge) => challenge.token == token && challenge.issued_at.elapsed() < CHALLENGE_LIFETIME,
+            None => false
+        }
+    }
+
+    fn write_basic_stat(&self, response: &mut Vec<u8>) {
+        write_string(response, &self.config.description_text());
+        write_string(response, "SMP");
+        write_string(response, "world");
+        write_string(response, "0");
+        write_string(response, &self.config.max_players.to_string());
+        response.extend_from_slice(&self.server_address.port().to_le_bytes());
+        write_string(response, &self.server_address.ip().to_string());
+    }
+
+    fn write_full_stat(&self, response: &mut Vec<u8>) {
+        response.extend_from_slice(b"splitnum\0\x80\0");
+
+        let values = [
+            ("hostname", self.config.description_text()),
+            ("gametype", "SMP".to_string()),
+            ("game_id", "MINECRAFT".to_string()),
+            ("version", ProtocolVersion::LATEST_SUPPORTED.name()),
+            ("plugins", String::new()),
+            ("map", "world".to_string()),
+            ("numplayers", "0".to_string()),
+            ("maxplayers", self.config.max_players.to_string()),
+            ("hostport", self.server_address.port().to_string()),
+            ("hostip", self.server_address.ip().to_string()),
+        ];
+
+        for (key, value) in values {
+            write_string(response, key);
+            write_string(response, &value);
+        }
+        response.push(0);
+
+        // no players are tracked yet, so the player list is empty
+        response.extend_from_slice(b"\x01player_\0\0");
+        response.push(0);
+    }
+
+    pub fn create(socket: UdpSocket, server_address: SocketAddr, config: Arc<Config>) -> QueryServer {
+        QueryServer {
+            socket,
+            server_address,
+            config,
+            challenges: HashMap::new(),
+        }
+    }
+}
+
+fn write_string(buf: &mut Vec<u8>, str: &str) {
+    buf.extend_from_slice(str.as_bytes());
+    buf.push(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT: &str = "127.0.0.1:50000";
+    const SESSION_ID: [u8; 4] = [0x00, 0x00, 0x00, 0x01];
+
+    async fn query_server() -> QueryServer {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let config = Config { max_players: 20, ..Config::default() };
+
+        QueryServer::create(socket, "127.0.0.1:25565".parse().unwrap(), Arc::new(config))
+    }
+
+    fn request(packet_type: u8, payload: &[u8]) -> Vec<u8> {
+        let mut request = MAGIC.to_vec();
+        request.push(packet_type);
+        request.extend_from_slice(&SESSION_ID);
+        request.extend_from_slice(payload);
+        request
+    }
+
+    // the null terminated strings of a response after its type and session id
+    fn strings(response: &[u8]) -> Vec<String> {
+        response[5..].split(|byte| *byte == 0).map(|str| String::from_utf8_lossy(str).into_owned()).collect()
+    }
+
+    fn challenge(query: &mut QueryServer) -> [u8; 4] {
+        let response = query.handle(&request(TYPE_HANDSHAKE, &[]), CLIENT.parse().unwrap()).unwrap();
+        assert_eq!(response[..5], [TYPE_HANDSHAKE, 0, 0, 0, 1]);
+
+        strings(&response)[0].parse::<i32>().unwrap().to_be_bytes()
+    }
+
+    #[tokio::test]
+    async fn basic_stats_are_sent_for_the_issued_challenge() {
+        let mut query = query_server().await;
+        let token = challenge(&mut query);
+
+        let response = query.handle(&request(TYPE_STAT, &token), CLIENT.parse().unwrap()).unwrap();
+        assert_eq!(response[..5], [TYPE_STAT, 0, 0, 0, 1]);
+
+        let strings = strings(&response);
+        assert_eq!(strings[0], query.config.description_text());
+        assert_eq!(strings[1..5], ["SMP", "world", "0", "20"]);
+        // the port is little endian, followed by the ip
+        assert_eq!(response[response.len() - 12..response.len() - 10], 25565u16.to_le_bytes());
+        assert!(response.ends_with(b"127.0.0.1\0"));
+    }
+
+    #[tokio::test]
+    async fn stats_are_not_sent_without_a_valid_challenge() {
+        let mut query = query_server().await;
+        let token = challenge(&mut query);
+        let wrong_token = (i32::from_be_bytes(token) ^ 1).to_be_bytes();
+
+        assert!(query.handle(&request(TYPE_STAT, &wrong_token), CLIENT.parse().unwrap()).is_none());
+        assert!(query.handle(&request(TYPE_STAT, &token), "127.0.0.1:50001".parse().unwrap()).is_none());
+    }
+}
@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::ConnectionRateLimit;
+
+// buckets are only pruned once there are this many, to keep most checks cheap
+const PRUNE_THRESHOLD: usize = 1024;
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+// a token bucket per ip address, each refilled with limit.connections tokens per limit.window
+pub struct RateLimiter {
+    limit: ConnectionRateLimit,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn create(limit: ConnectionRateLimit) -> RateLimiter {
+        RateLimiter {
+            limit,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // takes a token from the address' bucket, false when it's empty
+    pub fn allow(&self, address: IpAddr) -> bool {
+        if !self.limit.is_enabled() {
+            return true;
+        }
+
+        let now = Instant::now();
+        let capacity = self.limit.connections as f64;
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() >= PRUNE_THRESHOLD {
+            // a bucket that had time to refill completely is the same as no bucket
+            let window = self.limit.window;
+            buckets.retain(|_, bucket| now.duration_since(bucket.refilled_at) < window);
+        }
+
+        let bucket = buckets.entry(address).or_insert(Bucket { tokens: capacity, refilled_at: now });
+        let refill = now.duration_since(bucket.refilled_at).as_secs_f64() / self.window_secs() * capacity;
+        bucket.tokens = (bucket.tokens + refill).min(capacity);
+        bucket.refilled_at = now;
+
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    fn window_secs(&self) -> f64 {
+        self.limit.window.max(Duration::from_millis(1)).as_secs_f64()
+    }
+}
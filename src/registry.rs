@@ -0,0 +1,22 @@
+use crate::nbt::NbtWriter;
+
+// registries the client expects in the login packet, sent without any entries
+const REGISTRIES: &[&str] = &[
+    "minecraft:chat_type",
+    "minecraft:dimension_type",
+    "minecraft:worldgen/biome",
+];
+
+// the registry codec of the login packet, a compound with a compound per registry
+pub fn registry_codec() -> NbtWriter {
+    let mut nbt = NbtWriter::create();
+    nbt.begin_root();
+
+    for registry in REGISTRIES {
+        nbt.begin_compound(registry);
+        nbt.end_compound();
+    }
+
+    nbt.end_compound();
+    nbt
+}
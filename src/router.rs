@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+pub struct Router {
+    routes: HashMap<String, SocketAddr>,
+    default: Option<SocketAddr>,
+}
+
+impl Router {
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty() && self.default.is_none()
+    }
+
+    // exact matches win over wildcards like *.example.com, which are tried from the most specific
+    // one, and the default route is only used when nothing matches
+    pub fn route(&self, host: &str) -> Option<SocketAddr> {
+        let host = normalize_host(host);
+        if let Some(backend) = self.routes.get(&host) {
+            return Some(*backend);
+        }
+
+        let mut parent = host.as_str();
+        while let Some((_, rest)) = parent.split_once('.') {
+            if let Some(backend) = self.routes.get(&format!("*.{}", rest)) {
+                return Some(*backend);
+            }
+
+            parent = rest;
+        }
+
+        self.default
+    }
+
+    pub fn create(routes: &HashMap<String, SocketAddr>, default: Option<SocketAddr>) -> Router {
+        Router {
+            routes: routes.iter()
+                .map(|(host, backend)| (normalize_host(host), *backend))
+                .collect(),
+            default,
+        }
+    }
+}
+
+// lowercases the host and strips the port, the trailing dot of a fully qualified name and
+// anything clients append after a null byte
+pub fn normalize_host(host: &str) -> String {
+    let host = host.split('\0').next().unwrap_or_default();
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if !name.contains(':') && port.bytes().all(|b| b.is_ascii_digit()) => name,
+        _ => host
+    };
+
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backend(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn hosts_are_matched_without_case_or_port() {
+        let router = Router::create(&HashMap::from([("Play.Example.com".to_string(), backend(1))]), None);
+
+        assert_eq!(router.route("play.example.com"), Some(backend(1)));
+        assert_eq!(router.route("PLAY.example.COM:25565"), Some(backend(1)));
+        assert_eq!(router.route("play.example.com.\0FML3\0"), Some(backend(1)));
+    }
+
+    #[test]
+    fn unknown_hosts_have_no_route_without_a_default() {
+        let router = Router::create(&HashMap::from([("play.example.com".to_string(), backend(1))]), None);
+
+        assert_eq!(router.route("other.example.com"), None);
+        assert!(!router.is_empty());
+        assert!(Router::create(&HashMap::new(), None).is_empty());
+    }
+
+    #[test]
+    fn wildcards_match_subdomains_but_not_the_domain_itself() {
+        let routes = HashMap::from([("*.example.com".to_string(), backend(1))]);
+        let router = Router::create(&routes, Some(backend(9)));
+
+        assert_eq!(router.route("play.example.com"), Some(backend(1)));
+        assert_eq!(router.route("a.b.example.com"), Some(backend(1)));
+        assert_eq!(router.route("example.com"), Some(backend(9)));
+        assert_eq!(router.route("example.org"), Some(backend(9)));
+    }
+
+    #[test]
+    fn exact_routes_win_over_the_most_specific_wildcard() {
+        let routes = HashMap::from([
+            ("*.example.com".to_string(), backend(1)),
+            ("*.eu.example.com".to_string(), backend(2)),
+            ("lobby.eu.example.com".to_string(), backend(3)),
+        ]);
+        let router = Router::create(&routes, None);
+
+        assert_eq!(router.route("lobby.eu.example.com"), Some(backend(3)));
+        assert_eq!(router.route("survival.eu.example.com"), Some(backend(2)));
+        assert_eq!(router.route("survival.us.example.com"), Some(backend(1)));
+    }
+}
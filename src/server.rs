@@ -0,0 +1,323 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use tokio::sync::{Notify, Semaphore, watch};
+use tracing::{error, info, warn};
+
+use crate::bans::BanList;
+use crate::config::Config;
+use crate::encryption::ServerKey;
+use crate::outbound::PacketSender;
+use crate::packet::PacketWriter;
+use crate::protocol::ProtocolVersion;
+use crate::rate_limit::RateLimiter;
+use crate::router::Router;
+use crate::status::{FaviconError, load_favicon};
+use crate::whitelist::Whitelist;
+
+// a player in the play state, which other connections can send packets to
+pub struct OnlinePlayer {
+    pub name: String,
+    pub protocol: ProtocolVersion,
+    pub sender: PacketSender,
+}
+
+// what a connection holds of the bytes buffered by all of them
+struct BufferShare {
+    bytes: usize,
+    // set once the connection was told to go, so it isn't picked again before it's gone
+    evicted: bool,
+    eviction: Arc<Notify>,
+}
+
+// state shared by every connection of the server
+pub struct Server {
+    pub config: Arc<Config>,
+    pub connection_permits: Arc<Semaphore>,
+    pub backend_permits: Arc<Semaphore>,
+    pub rate_limiter: RateLimiter,
+    // replaced as a whole when the routes are reloaded
+    router: RwLock<Arc<Router>>,
+    // likewise reloaded, None when whitelisting is disabled
+    whitelist: RwLock<Option<Arc<Whitelist>>>,
+    bans: RwLock<Arc<BanList>>,
+    // only generated in online mode
+    pub key: OnceLock<ServerKey>,
+    // logins are refused until the server finished initializing
+    ready: AtomicBool,
+    // starts out as configured and can be toggled while running
+    maintenance: AtomicBool,
+    // bytes received but not parsed yet, summed over all connections
+    buffered_bytes: AtomicUsize,
+    // the share of each connection in buffered_bytes, by connection id
+    buffer_shares: Mutex<HashMap<u64, BufferShare>>,
+    // connections in the play state, by connection id
+    players: Mutex<HashMap<u64, OnlinePlayer>>,
+    pub favicon: Option<String>,
+    // set once when the server stops, connections disconnect their clients when it changes
+    shutdown: watch::Sender<bool>,
+}
+
+impl Server {
+    pub fn create(config: Config) -> Server {
+        Server {
+            connection_permits: Arc::new(Semaphore::new(config.max_connections)),
+            backend_permits: Arc::new(Semaphore::new(config.max_backend_connections)),
+            rate_limiter: RateLimiter::create(config.connection_rate_limit),
+            router: RwLock::new(Arc::new(Router::create(&config.routes, config.backend))),
+            whitelist: RwLock::new(load_whitelist(&config).map(Arc::new)),
+            bans: RwLock::new(Arc::new(load_bans(&config))),
+            key: OnceLock::new(),
+            ready: AtomicBool::new(false),
+            maintenance: AtomicBool::new(config.maintenance.enabled),
+            buffered_bytes: AtomicUsize::new(0),
+            buffer_shares: Mutex::new(HashMap::new()),
+            players: Mutex::new(HashMap::new()),
+            favicon: match load_favicon(&config.favicon) {
+                Ok(favicon) => Some(favicon),
+                Err(FaviconError::Io(e)) if e.kind() == ErrorKind::NotFound => None,
+                Err(e) => {
+                    warn!("ignoring favicon {}: {}", config.favicon.display(), e);
+                    None
+                }
+            },
+            shutdown: watch::Sender::new(false),
+            config: Arc::new(config),
+        }
+    }
+
+    // does the slow part of the setup, meant to run while connections are already accepted
+    pub fn initialize(&self) {
+        if self.config.online_mode {
+            let key = ServerKey::generate().expect("failed to generate the server key");
+            let _ = self.key.set(key);
+        }
+
+        self.set_ready();
+    }
+
+    pub fn router(&self) -> Arc<Router> {
+        self.router.read().unwrap().clone()
+    }
+
+    pub fn set_router(&self, router: Router) {
+        *self.router.write().unwrap() = Arc::new(router);
+    }
+
+    pub fn whitelist(&self) -> Option<Arc<Whitelist>> {
+        self.whitelist.read().unwrap().clone()
+    }
+
+    pub fn set_whitelist(&self, whitelist: Option<Whitelist>) {
+        *self.whitelist.write().unwrap() = whitelist.map(Arc::new);
+    }
+
+    pub fn bans(&self) -> Arc<BanList> {
+        self.bans.read().unwrap().clone()
+    }
+
+    pub fn set_bans(&self, bans: BanList) {
+        *self.bans.write().unwrap() = Arc::new(bans);
+    }
+
+    pub fn buffered_bytes(&self) -> usize {
+        self.buffered_bytes.load(Ordering::Relaxed)
+    }
+
+    // starts accounting the buffer of a connection, which is notified when it has to free it
+    pub fn track_buffered(&self, connection_id: u64) -> Arc<Notify> {
+        let eviction = Arc::new(Notify::new());
+        self.buffer_shares.lock().unwrap().insert(connection_id, BufferShare {
+            bytes: 0,
+            evicted: false,
+            eviction: eviction.clone(),
+        });
+
+        eviction
+    }
+
+    // records how many bytes the connection buffers, returning the total of all connections
+    pub fn set_buffered(&self, connection_id: u64, bytes: usize) -> usize {
+        let mut shares = self.buffer_shares.lock().unwrap();
+        let previous = match shares.get_mut(&connection_id) {
+            Some(share) => std::mem::replace(&mut share.bytes, bytes),
+            None => return self.buffered_bytes(),
+        };
+
+        if bytes >= previous {
+            self.buffered_bytes.fetch_add(bytes - previous, Ordering::Relaxed) + bytes - previous
+        } else {
+            self.buffered_bytes.fetch_sub(previous - bytes, Ordering::Relaxed) - (previous - bytes)
+        }
+    }
+
+    pub fn untrack_buffered(&self, connection_id: u64) {
+        if let Some(share) = self.buffer_shares.lock().unwrap().remove(&connection_id) {
+            self.buffered_bytes.fetch_sub(share.bytes, Ordering::Relaxed);
+        }
+    }
+
+    // tells the connections holding the most to go until what the others hold fits in max_bytes,
+    // returning how many were told
+    pub fn evict_largest_buffers(&self, max_bytes: usize) -> usize {
+        let mut shares = self.buffer_shares.lock().unwrap();
+        let mut remaining: usize = shares.values().filter(|share| !share.evicted).map(|share| share.bytes).sum();
+
+        let mut largest: Vec<&mut BufferShare> = shares.values_mut()
+            .filter(|share| !share.evicted && share.bytes > 0)
+            .collect();
+        largest.sort_by_key(|share| Reverse(share.bytes));
+
+        let mut evicted = 0;
+        for share in largest {
+            if remaining <= max_bytes {
+                break;
+            }
+
+            remaining -= share.bytes;
+            share.evicted = true;
+            share.eviction.notify_one();
+            evicted += 1;
+        }
+
+        evicted
+    }
+
+    // connections accepted and not closed yet, in any state
+    pub fn open_connections(&self) -> usize {
+        self.config.max_connections - self.connection_permits.available_permits()
+    }
+
+    pub fn online_players(&self) -> usize {
+        self.players.lock().unwrap().len()
+    }
+
+    pub fn player_joined(&self, connection_id: u64, player: OnlinePlayer) {
+        self.players.lock().unwrap().insert(connection_id, player);
+    }
+
+    pub fn player_left(&self, connection_id: u64) {
+        self.players.lock().unwrap().remove(&connection_id);
+    }
+
+    // sends every player the packet built for their protocol version, players that can't keep
+    // up with their packets miss it
+    pub fn broadcast<F: Fn(ProtocolVersion) -> PacketWriter>(&self, packet: F) {
+        for player in self.players.lock().unwrap().values() {
+            player.sender.try_send(packet(player.protocol));
+        }
+    }
+
+    pub fn shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    pub fn shutdown_signal(&self) -> watch::Receiver<bool> {
+        self.shutdown.subscribe()
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Acquire)
+    }
+
+    pub fn set_ready(&self) {
+        self.ready.store(true, Ordering::Release);
+    }
+
+    pub fn in_maintenance(&self) -> bool {
+        self.maintenance.load(Ordering::Acquire)
+    }
+
+    pub fn set_maintenance(&self, enabled: bool) {
+        self.maintenance.store(enabled, Ordering::Release);
+    }
+}
+
+// a whitelist that can't be loaded lets nobody in rather than everyone
+pub fn load_whitelist(config: &Config) -> Option<Whitelist> {
+    let path = config.whitelist.as_ref()?;
+
+    match Whitelist::load(path) {
+        Ok(whitelist) => {
+            info!("loaded {} whitelisted players", whitelist.len());
+            Some(whitelist)
+        }
+        Err(e) => {
+            error!("failed to load the whitelist {}: {}", path.display(), e);
+            Some(Whitelist::empty())
+        }
+    }
+}
+
+// unlike the whitelist, bans that can't be loaded leave everyone in
+pub fn load_bans(config: &Config) -> BanList {
+    let path = match &config.bans {
+        Some(path) => path,
+        None => return BanList::default()
+    };
+
+    match BanList::load(path) {
+        Ok(bans) => {
+            info!("loaded {} bans", bans.len());
+            bans
+        }
+        Err(e) => {
+            error!("failed to load the bans {}: {}", path.display(), e);
+            BanList::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+
+    #[test]
+    fn reloaded_routes_replace_the_old_ones_at_once() {
+        let server = Server::create(Config::default());
+        let old = SocketAddr::from(([127, 0, 0, 1], 1));
+        let new = SocketAddr::from(([127, 0, 0, 1], 2));
+
+        server.set_router(Router::create(&HashMap::from([("*.example.com".to_string(), old)]), None));
+        let before = server.router();
+
+        server.set_router(Router::create(&HashMap::new(), Some(new)));
+
+        // connections routing with the router they got keep seeing the old routes
+        assert_eq!(before.route("play.example.com"), Some(old));
+        assert_eq!(server.router().route("play.example.com"), Some(new));
+    }
+
+    #[tokio::test]
+    async fn the_largest_buffers_are_evicted_until_the_rest_fits() {
+        let server = Server::create(Config::default());
+        let evictions: Vec<_> = (0..4).map(|id| server.track_buffered(id)).collect();
+
+        for (id, bytes) in [(0, 10), (1, 50), (2, 30), (3, 20)] {
+            server.set_buffered(id, bytes);
+        }
+        assert_eq!(server.buffered_bytes(), 110);
+
+        // dropping 50 and 30 leaves 30, dropping 50 alone would leave 60
+        assert_eq!(server.evict_largest_buffers(40), 2);
+
+        let notified = |id: usize| {
+            let notified = evictions[id].notified();
+            tokio::pin!(notified);
+            notified.enable()
+        };
+        assert_eq!((0..4).map(notified).collect::<Vec<_>>(), [false, true, true, false]);
+
+        // the evicted ones aren't picked again while they're going
+        assert_eq!(server.evict_largest_buffers(40), 0);
+
+        server.untrack_buffered(1);
+        server.untrack_buffered(2);
+        assert_eq!(server.buffered_bytes(), 30);
+    }
+}
@@ -0,0 +1,191 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::packet::{EncodingError, PacketType, PacketWriter};
+use crate::packet::payload::{Encodable, StatusPing};
+use crate::protocol::ProtocolVersion;
+
+// limit of the json in a status response
+const MAX_STATUS_LENGTH: usize = 32767;
+
+// the server list ping response, as sent by us or by a backend server
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct StatusResponse {
+    pub version: Version,
+    pub players: Players,
+    // a chat component, kept as raw JSON since it can be a string, an array or an object
+    pub description: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub favicon: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct Version {
+    pub name: String,
+    pub protocol: i32,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct Players {
+    pub max: i32,
+    pub online: i32,
+    #[serde(default)]
+    pub sample: Vec<PlayerSample>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct PlayerSample {
+    pub name: String,
+    pub id: String,
+}
+
+impl StatusResponse {
+    pub fn parse(json: &str) -> Result<StatusResponse, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("status response is always serializable")
+    }
+}
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const FAVICON_SIZE: u32 = 64;
+
+#[derive(Debug)]
+pub enum FaviconError {
+    Io(std::io::Error),
+    NotPng,
+    WrongSize(u32, u32),
+}
+
+impl Display for FaviconError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self, f)
+    }
+}
+
+impl Error for FaviconError {}
+
+// reads a 64x64 png and turns it into the data url the status response expects
+pub fn load_favicon<P: AsRef<Path>>(path: P) -> Result<String, FaviconError> {
+    let png = std::fs::read(path).map_err(FaviconError::Io)?;
+
+    // the signature is followed by the IHDR chunk, which starts with the width and height
+    if png.len() < 24 || &png[0..8] != PNG_SIGNATURE || &png[12..16] != b"IHDR" {
+        return Err(FaviconError::NotPng);
+    }
+
+    let width = u32::from_be_bytes(png[16..20].try_into().unwrap());
+    let height = u32::from_be_bytes(png[20..24].try_into().unwrap());
+    if width != FAVICON_SIZE || height != FAVICON_SIZE {
+        return Err(FaviconError::WrongSize(width, height));
+    }
+
+    Ok(format!("data:image/png;base64,{}", base64::engine::general_purpose::STANDARD.encode(png)))
+}
+
+// fails when the response doesn't fit in a string, which a large favicon can cause
+pub fn build_status_response(status: &StatusResponse, protocol: ProtocolVersion) -> Result<PacketWriter, EncodingError> {
+    let json = status.to_json();
+
+    let mut packet = PacketWriter::create(json.len() + 8);
+    packet.write_packet_type(PacketType::StatusClientboundResponse, protocol);
+    packet.write_string_checked(&json, MAX_STATUS_LENGTH)?;
+    Ok(packet)
+}
+
+pub fn build_pong(payload: i64, protocol: ProtocolVersion) -> PacketWriter {
+    let mut packet = PacketWriter::create(9);
+    packet.write_packet_type(PacketType::StatusClientboundPong, protocol);
+    StatusPing { payload }.encode(&mut packet);
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // what a vanilla server answers, with the keys of the description sorted the way they're written back
+    const VANILLA_STATUS: &str = r#"{"version":{"name":"1.19.4","protocol":762},"players":{"max":20,"online":1,"sample":[{"name":"Notch","id":"069a79f4-44e9-4726-a5be-fca90e38aaf5"}]},"description":{"extra":[{"color":"gold","text":"!"}],"text":"A Minecraft Server"},"favicon":"data:image/png;base64,iVBORw0KGgo="}"#;
+
+    #[test]
+    fn backend_status_responses_are_read_and_written_unchanged() {
+        let status = StatusResponse::parse(VANILLA_STATUS).unwrap();
+
+        assert_eq!(status.version, Version { name: "1.19.4".to_string(), protocol: 762 });
+        assert_eq!(status.players.sample[0].name, "Notch");
+        assert_eq!(status.description["extra"][0]["color"], "gold");
+        assert_eq!(status.favicon.as_deref(), Some("data:image/png;base64,iVBORw0KGgo="));
+
+        assert_eq!(status.to_json(), VANILLA_STATUS);
+        assert_eq!(StatusResponse::parse(&status.to_json()).unwrap(), status);
+    }
+
+    #[test]
+    fn optional_status_fields_may_be_left_out() {
+        let status = StatusResponse::parse(r#"{"version":{"name":"1.20","protocol":763},"players":{"max":5,"online":0},"description":"plain"}"#).unwrap();
+
+        assert!(status.players.sample.is_empty());
+        assert_eq!(status.description, "plain");
+        assert_eq!(status.favicon, None);
+    }
+
+    #[test]
+    fn status_responses_have_the_shape_clients_expect() {
+        let status = StatusResponse {
+            version: Version { name: "1.20".to_string(), protocol: 763 },
+            players: Players { max: 100, online: 3, sample: Vec::new() },
+            description: serde_json::json!({ "text": "hello" }),
+            favicon: None,
+        };
+
+        let json: Value = serde_json::from_str(&status.to_json()).unwrap();
+        assert_eq!(json, serde_json::json!({
+            "version": { "name": "1.20", "protocol": 763 },
+            "players": { "max": 100, "online": 3, "sample": [] },
+            "description": { "text": "hello" },
+        }));
+    }
+
+    // the signature and the start of an IHDR chunk, which is all that's checked
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut png = PNG_SIGNATURE.to_vec();
+        png.extend_from_slice(&13u32.to_be_bytes());
+        png.extend_from_slice(b"IHDR");
+        png.extend_from_slice(&width.to_be_bytes());
+        png.extend_from_slice(&height.to_be_bytes());
+        png.extend_from_slice(&[8, 6, 0, 0, 0]);
+        png
+    }
+
+    fn load(name: &str, contents: &[u8]) -> Result<String, FaviconError> {
+        let path = std::env::temp_dir().join(format!("funny-proxy-{}-{}.png", name, std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+
+        let favicon = load_favicon(&path);
+        std::fs::remove_file(&path).unwrap();
+        favicon
+    }
+
+    #[test]
+    fn favicons_are_encoded_as_data_urls() {
+        let favicon = load("icon", &png(64, 64)).unwrap();
+        let encoded = favicon.strip_prefix("data:image/png;base64,").unwrap();
+
+        assert_eq!(base64::engine::general_purpose::STANDARD.decode(encoded).unwrap(), png(64, 64));
+    }
+
+    #[test]
+    fn favicons_must_be_64x64_pngs() {
+        assert!(matches!(load("large", &png(128, 64)), Err(FaviconError::WrongSize(128, 64))));
+        assert!(matches!(load("text", b"definitely not an image, just text"), Err(FaviconError::NotPng)));
+        assert!(matches!(load("short", &PNG_SIGNATURE[..4]), Err(FaviconError::NotPng)));
+        assert!(matches!(load_favicon(std::env::temp_dir().join("funny-proxy-no-icon.png")), Err(FaviconError::Io(_))));
+    }
+}
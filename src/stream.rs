@@ -0,0 +1,78 @@
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+
+// address given to clients of the unix socket, which are always on the same host
+pub const UNIX_PEER: SocketAddr = SocketAddr::new(std::net::IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+
+// a client connection from either of the listeners
+pub enum ClientStream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl ClientStream {
+    // the address the client connected to, which is also the loopback one for the unix socket
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            ClientStream::Tcp(stream) => stream.local_addr(),
+            #[cfg(unix)]
+            ClientStream::Unix(_) => Ok(UNIX_PEER)
+        }
+    }
+
+    // what to call the client in logs, the socket path for unix sockets
+    pub fn describe_peer(&self, peer: SocketAddr) -> String {
+        match self {
+            ClientStream::Tcp(_) => peer.to_string(),
+            #[cfg(unix)]
+            ClientStream::Unix(stream) => match stream.local_addr().ok().and_then(|address| address.as_pathname().map(|path| path.to_owned())) {
+                Some(path) => path.display().to_string(),
+                None => "unix socket".to_string()
+            }
+        }
+    }
+}
+
+impl AsyncRead for ClientStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            ClientStream::Unix(stream) => Pin::new(stream).poll_read(cx, buf)
+        }
+    }
+}
+
+impl AsyncWrite for ClientStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ClientStream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            ClientStream::Unix(stream) => Pin::new(stream).poll_write(cx, buf)
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(unix)]
+            ClientStream::Unix(stream) => Pin::new(stream).poll_flush(cx)
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(unix)]
+            ClientStream::Unix(stream) => Pin::new(stream).poll_shutdown(cx)
+        }
+    }
+}
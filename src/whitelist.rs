@@ -0,0 +1,78 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+use serde::Deserialize;
+use uuid::Uuid;
+
+// an entry of the whitelist file, which has the format of the vanilla whitelist.json
+#[derive(Deserialize)]
+struct WhitelistEntry {
+    name: String,
+    #[serde(default)]
+    uuid: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum WhitelistError {
+    Io(std::io::Error),
+    Parse(serde_json::Error),
+    InvalidUuid(String),
+}
+
+impl Display for WhitelistError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WhitelistError::Io(e) => Display::fmt(e, f),
+            WhitelistError::Parse(e) => Display::fmt(e, f),
+            WhitelistError::InvalidUuid(uuid) => write!(f, "invalid uuid {}", uuid)
+        }
+    }
+}
+
+impl Error for WhitelistError {}
+
+pub struct Whitelist {
+    entries: Vec<(String, Option<Uuid>)>,
+}
+
+impl Whitelist {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Whitelist, WhitelistError> {
+        let json = std::fs::read_to_string(path).map_err(WhitelistError::Io)?;
+        let entries: Vec<WhitelistEntry> = serde_json::from_str(&json).map_err(WhitelistError::Parse)?;
+
+        let entries = entries.into_iter()
+            .map(|entry| match entry.uuid {
+                Some(uuid) => match uuid.parse() {
+                    Ok(parsed) => Ok((entry.name, Some(parsed))),
+                    Err(_) => Err(WhitelistError::InvalidUuid(uuid))
+                },
+                None => Ok((entry.name, None))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Whitelist { entries })
+    }
+
+    // refuses everyone, for when the whitelist file can't be read
+    pub fn empty() -> Whitelist {
+        Whitelist { entries: Vec::new() }
+    }
+
+    // the uuid is only given when it was verified, since anyone could claim any uuid otherwise,
+    // and then takes precedence over the name of entries that have one
+    pub fn allows(&self, name: &str, uuid: Option<Uuid>) -> bool {
+        self.entries.iter().any(|(allowed_name, allowed_uuid)| match (allowed_uuid, uuid) {
+            (Some(allowed_uuid), Some(uuid)) => *allowed_uuid == uuid,
+            _ => allowed_name.eq_ignore_ascii_case(name)
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}